@@ -1,22 +1,109 @@
+use net::{Fingerprint, KeepAliveConfig, Passphrase, PrivateKey, PublicKey, Screen, VsockAddr};
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
-use std::fmt::{self, Formatter};
+use std::fmt::{self, Display, Formatter};
 use std::path::PathBuf;
 use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddr};
 
-pub type Config = HashMap<String, Server>;
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    // Name to present to the server, defaults to the hostname.
+    pub name: Option<String>,
+    pub screen: Option<Screen>,
+    // In reverse-connect mode, wait for the server to connect to this address instead of connecting to the servers below.
+    pub listen_address: Option<SocketAddr>,
+    // Required by listen-address, the certificate servers connecting to us have to present, or its fingerprint.
+    pub certificate_path: Option<PathBuf>,
+    pub certificate_fingerprint: Option<Fingerprint>,
+    // The identity presented to servers requiring client certificates, and to the server in reverse-connect mode.
+    pub identity_path: Option<PathBuf>,
+    #[serde(default)]
+    pub identity_password: String,
+    // The same as PEM files, used instead of identity-path if set.
+    pub identity_certificate_path: Option<PathBuf>,
+    pub identity_key_path: Option<PathBuf>,
+    // Presented to servers using the noise transport, generated on every start if unset.
+    pub noise_private_key: Option<PrivateKey>,
+    #[serde(default)]
+    pub keep_alive: KeepAliveConfig,
+    // Largest frame sent to or accepted from servers, in bytes. Defaults to 65536.
+    pub max_frame_size: Option<u32>,
+    #[serde(flatten)]
+    pub servers: HashMap<String, Server>,
+}
 
 #[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Server {
     pub server_address: ServerAddress,
-    pub certificate_path: PathBuf,
+    // Either this or certificate-fingerprint is required unless connecting to a Unix or vsock socket,
+    // which use TLS only if either is set.
+    pub certificate_path: Option<PathBuf>,
+    // Pins the server certificate instead, regardless of names and expiry.
+    pub certificate_fingerprint: Option<Fingerprint>,
+    // Name the certificate of the server is checked against, defaults to the host of the server address.
+    pub server_name: Option<String>,
+    #[serde(default)]
+    pub transport: Transport,
+    // SOCKS5 or HTTP CONNECT proxy to reach the server through, TLS still ends at the server.
+    pub proxy: Option<Proxy>,
+    // Required by the noise transport instead of certificate-path or certificate-fingerprint.
+    pub noise: Option<Noise>,
+}
+
+// At least one of both has to be set, the server is authenticated by either.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Noise {
+    // As logged by the server on startup.
+    pub public_key: Option<PublicKey>,
+    pub passphrase: Option<Passphrase>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    // TLS over TCP.
+    #[default]
+    Tcp,
+    // QUIC, with mouse movement on a stream of its own.
+    Quic,
+    // WebSocket over TLS, for networks which only let HTTP(S) through.
+    #[serde(rename = "websocket")]
+    WebSocket,
+    // Noise over TCP, authenticated by keys and/or a passphrase instead of certificates.
+    Noise,
+}
+
+impl Server {
+    // None for Unix and vsock sockets without an explicit server name.
+    pub fn tls_name(&self) -> Option<&str> {
+        match (&self.server_name, &self.server_address) {
+            (Some(name), _) => Some(name),
+            (None, ServerAddress::Host { host, .. }) => Some(host),
+            (None, ServerAddress::Unix(_)) | (None, ServerAddress::Vsock(_)) => None,
+        }
+    }
 }
 
 #[derive(Clone)]
-pub struct ServerAddress {
-    pub host: String,
-    pub port: u16,
+pub enum ServerAddress {
+    Host { host: String, port: u16 },
+    Unix(PathBuf),
+    // The host is vsock:host:PORT as seen from a virtual machine.
+    Vsock(VsockAddr),
+}
+
+impl Display for ServerAddress {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Host { host, port } => write!(f, "{}", HostPort(host, *port)),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Vsock(address) => write!(f, "{}", address),
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for ServerAddress {
@@ -34,29 +121,191 @@ impl<'de> Visitor<'de> for ServerAddressVisitor {
     type Value = ServerAddress;
 
     fn expecting(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "a server description (hostname:port)")
+        write!(
+            f,
+            "a server description (hostname:port, unix:/path or vsock:cid:port)"
+        )
     }
 
     fn visit_str<E>(self, data: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        let err = || E::custom("Invalid server description");
+        if let Some(path) = data.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(E::custom("Empty Unix socket path"));
+            }
 
-        let mut split = data.split(':');
-        let host = split.next().ok_or_else(err)?;
-        let port = split
-            .next()
-            .and_then(|data| data.parse().ok())
-            .ok_or_else(err)?;
+            return Ok(ServerAddress::Unix(path.into()));
+        }
 
-        if split.next().is_some() {
-            return Err(E::custom("Extraneous data"));
+        if let Some(address) = data.strip_prefix("vsock:") {
+            return address.parse().map(ServerAddress::Vsock).map_err(E::custom);
         }
 
-        Ok(ServerAddress {
-            host: host.to_owned(),
+        let (host, port) = parse_host_port(data).map_err(E::custom)?;
+        Ok(ServerAddress::Host { host, port })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+#[derive(Clone)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    // Username and password, taken verbatim from the URL.
+    pub credentials: Option<(String, String)>,
+}
+
+// Leaves out the credentials, as this ends up in logs.
+impl Display for Proxy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        };
+
+        write!(f, "{}://{}", scheme, HostPort(&self.host, self.port))
+    }
+}
+
+impl<'de> Deserialize<'de> for Proxy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ProxyVisitor)
+    }
+}
+
+struct ProxyVisitor;
+
+impl<'de> Visitor<'de> for ProxyVisitor {
+    type Value = Proxy;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a proxy URL (socks5://[user:password@]host:port or http://[user:password@]host:port)"
+        )
+    }
+
+    fn visit_str<E>(self, data: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        let (kind, rest) = if let Some(rest) = data.strip_prefix("socks5://") {
+            (ProxyKind::Socks5, rest)
+        } else if let Some(rest) = data.strip_prefix("socks5h://") {
+            // The host is always resolved by the proxy anyway.
+            (ProxyKind::Socks5, rest)
+        } else if let Some(rest) = data.strip_prefix("http://") {
+            (ProxyKind::Http, rest)
+        } else {
+            return Err(E::custom("Unsupported proxy scheme"));
+        };
+
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        let (credentials, address) = match rest.rsplit_once('@') {
+            Some((credentials, address)) => {
+                let (username, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+                (Some((username.to_owned(), password.to_owned())), address)
+            }
+            None => (None, rest),
+        };
+
+        let (host, port) = parse_host_port(address).map_err(E::custom)?;
+        Ok(Proxy {
+            kind,
+            host,
             port,
+            credentials,
         })
     }
 }
+
+// Parses host:port, where IPv6 literals have to be enclosed in brackets, as in [fe80::1]:5258.
+fn parse_host_port(data: &str) -> Result<(String, u16), &'static str> {
+    let (host, port) = match data.strip_prefix('[') {
+        Some(data) => {
+            let (host, port) = data.split_once(']').ok_or("Missing closing bracket")?;
+            // Link-local addresses may carry a zone, as in fe80::1%eth0.
+            let address = host.split('%').next().unwrap_or_default();
+            if address.parse::<Ipv6Addr>().is_err() {
+                return Err("Invalid IPv6 address");
+            }
+
+            (host, port.strip_prefix(':').ok_or("Missing port")?)
+        }
+        None => {
+            let (host, port) = data.rsplit_once(':').ok_or("Missing port")?;
+            if host.contains(':') {
+                return Err("IPv6 addresses have to be enclosed in brackets, as in [::1]:5258");
+            }
+
+            (host, port)
+        }
+    };
+
+    if host.is_empty() {
+        return Err("Missing host");
+    }
+
+    let port = port.parse().map_err(|_| "Invalid port")?;
+    Ok((host.to_owned(), port))
+}
+
+// The inverse of parse_host_port.
+struct HostPort<'a>(&'a str, u16);
+
+impl Display for HostPort<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.0.contains(':') {
+            write!(f, "[{}]:{}", self.0, self.1)
+        } else {
+            write!(f, "{}:{}", self.0, self.1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_port() {
+        assert_eq!(
+            parse_host_port("localhost:5258"),
+            Ok(("localhost".to_owned(), 5258))
+        );
+        assert_eq!(parse_host_port("[::1]:5258"), Ok(("::1".to_owned(), 5258)));
+        assert_eq!(
+            parse_host_port("[fe80::1%eth0]:1"),
+            Ok(("fe80::1%eth0".to_owned(), 1))
+        );
+
+        assert_eq!(
+            parse_host_port("::1"),
+            Err("IPv6 addresses have to be enclosed in brackets, as in [::1]:5258")
+        );
+        assert_eq!(parse_host_port("localhost"), Err("Missing port"));
+        assert_eq!(parse_host_port("[::1]"), Err("Missing port"));
+        assert_eq!(parse_host_port("[::1:5258"), Err("Missing closing bracket"));
+        assert!(parse_host_port("[localhost]:5258").is_err());
+        assert!(parse_host_port(":5258").is_err());
+    }
+
+    #[test]
+    fn host_port_display() {
+        for data in ["localhost:5258", "[::1]:5258", "[fe80::1%eth0]:1"] {
+            let (host, port) = parse_host_port(data).unwrap();
+            assert_eq!(HostPort(&host, port).to_string(), data);
+        }
+    }
+}
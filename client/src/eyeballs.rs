@@ -0,0 +1,83 @@
+use anyhow::{Context, Error};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time;
+
+// How long an attempt gets before the next address is tried alongside it, as recommended by RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Connects to any of the addresses the host resolves to, racing IPv6 and IPv4 attempts ("Happy Eyeballs").
+pub async fn connect(host: &str, port: u16) -> Result<TcpStream, Error> {
+    let addresses: Vec<_> = lookup_host((host, port))
+        .await
+        .with_context(|| format!("Failed to resolve {}", host))?
+        .collect();
+    let addresses = interleave(addresses);
+    log::debug!("{} resolved to {:?}", host, addresses);
+
+    let mut pending = addresses.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    attempts.extend(pending.next().map(attempt));
+    while !attempts.is_empty() {
+        tokio::select! {
+            Some((address, result)) = attempts.next() => match result {
+                Ok(stream) => {
+                    log::debug!("Connected to {}", address);
+                    return Ok(stream);
+                }
+                Err(err) => {
+                    log::debug!("Connecting to {} failed: {}", address, err);
+                    last_error = Some(err);
+
+                    // No point in waiting for the delay if the attempt failed.
+                    attempts.extend(pending.next().map(attempt));
+                }
+            },
+            _ = time::sleep(ATTEMPT_DELAY), if !pending.as_slice().is_empty() => {
+                attempts.extend(pending.next().map(attempt));
+            }
+        }
+    }
+
+    match last_error {
+        Some(err) => Err(Error::new(err).context(format!("Failed to connect to {}", host))),
+        None => Err(anyhow::anyhow!("{} did not resolve to any address", host)),
+    }
+}
+
+async fn attempt(address: SocketAddr) -> (SocketAddr, Result<TcpStream, io::Error>) {
+    log::debug!("Trying {}", address);
+
+    let result = time::timeout(ATTEMPT_TIMEOUT, TcpStream::connect(address))
+        .await
+        .unwrap_or_else(|_| Err(io::Error::new(ErrorKind::TimedOut, "Connection timed out")));
+
+    (address, result)
+}
+
+// Alternates between address families, starting with the one the resolver put first.
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let preferred = match addresses.first() {
+        Some(address) => address.is_ipv6(),
+        None => return addresses,
+    };
+
+    let (mut first, mut second): (VecDeque<_>, VecDeque<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == preferred);
+
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    while !first.is_empty() || !second.is_empty() {
+        interleaved.extend(first.pop_front());
+        interleaved.extend(second.pop_front());
+    }
+
+    interleaved
+}
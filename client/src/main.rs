@@ -1,90 +1,759 @@
 mod config;
+mod eyeballs;
+mod proxy;
+mod quic;
+mod tls;
 
 use anyhow::{Context, Error};
-use config::{Config, Server};
-use input::EventWriter;
-use net::{self, Message, PROTOCOL_VERSION};
+use config::{Config, Server, ServerAddress, Transport};
+use input::{Direction, Event, EventWriter, KeyKind};
+use net::{
+    self, Capabilities, DatagramDirection, DatagramOpener, DatagramSealer, DatagramSetup, Framing,
+    Duplex, Hello, KeepAlive, Message, Noise, PrivateKey, Report, Resume, Role, SessionTicket,
+    UnknownCode, WebSocket,
+};
+#[cfg(target_os = "linux")]
+use net::VsockStream;
+use quinn::{RecvStream, SendStream};
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::env;
+use std::io;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+use tls::{Identity, Trust};
 use tokio::fs;
-use tokio::io::BufReader;
-use tokio::net::TcpStream;
+use tokio::io::{self as tokio_io, AsyncRead, AsyncWrite, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
 use tokio::time;
-use tokio_native_tls::native_tls::{Certificate, TlsConnector};
 use futures::{future::select_all, FutureExt};
 
-async fn try_connect(name: String, server: Server) -> Result<(String, Server, Certificate, TcpStream), Error> {
-    let certificate = fs::read(&server.certificate_path).await
-        .context("Failed to read certificate")?;
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
 
-    let certificate = Certificate::from_der(&certificate)
-        .or_else(|_| Certificate::from_pem(&certificate))
-        .context("Failed to parse certificate")?;
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
 
-    let (host, port) = (&server.server_address.host, server.server_address.port);
+// A connection ready for the handshake, along with the address to exchange datagrams with and the QUIC connection, if any.
+type Opened = (Box<dyn Stream>, Option<SocketAddr>, Option<quinn::Connection>);
 
-    log::info!("Attempting connection to {} ({}:{})", name, host, port);
-    let stream = TcpStream::connect((host.as_str(), port)).await?;
+// How long to wait between attempts to resume a session.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
 
-    Ok((name, server, certificate, stream))
+// What outlives a connection, so that the session can be resumed on the next one.
+#[derive(Default)]
+struct State {
+    // Kept around, so that keys pressed when the connection was lost stay pressed until the session is resumed.
+    writer: Option<EventWriter>,
+    ticket: Option<SessionTicket>,
+    // The last sequenced frame written.
+    received: u64,
+    // Keys and buttons we have pressed and not released yet.
+    pressed: HashSet<KeyKind>,
+}
+
+enum Connection {
+    // TLS is set up once the race between servers is won.
+    Tcp(TcpStream, Trust),
+    // Same as above, with the URL the WebSocket upgrade is requested for.
+    WebSocket(TcpStream, Trust, String),
+    // Unix and vsock sockets are protected by other means, TLS is only used if a certificate is configured.
+    #[cfg(unix)]
+    Local(Box<dyn Stream>, Option<Trust>),
+    // QUIC has TLS built in, the stream is the bidirectional one carrying everything but mouse movement.
+    Quic(quinn::Connection, Duplex<RecvStream, SendStream>),
+    // The Noise handshake is done once the race between servers is won, like TLS.
+    Noise(TcpStream, config::Noise),
+}
+
+async fn try_connect(name: String, server: Server) -> Result<(String, Server, Connection), Error> {
+    let trust = Trust::load(&server).await?;
+
+    log::info!("Attempting connection to {} ({})", name, server.server_address);
+    if server.proxy.is_some() && !matches!(server.server_address, ServerAddress::Host { .. }) {
+        return Err(anyhow::anyhow!("A proxy requires a host and port as the server address"));
+    }
+
+    let connection = match (&server.server_address, server.transport) {
+        (ServerAddress::Host { host, port }, Transport::Tcp) => {
+            let trust = trust.context("No certificate-path or certificate-fingerprint set")?;
+            let stream = connect_tcp(&server, host, *port).await?;
+            Connection::Tcp(stream, trust)
+        }
+        (ServerAddress::Host { host, port }, Transport::WebSocket) => {
+            let trust = trust.context("No certificate-path or certificate-fingerprint set")?;
+            let stream = connect_tcp(&server, host, *port).await?;
+            Connection::WebSocket(stream, trust, format!("wss://{}:{}/", host, port))
+        }
+        (ServerAddress::Host { .. }, Transport::Quic) if server.proxy.is_some() => {
+            return Err(anyhow::anyhow!("QUIC can't be used through a proxy"))
+        }
+        (ServerAddress::Host { host, port }, Transport::Quic) => {
+            let trust = trust.context("No certificate-path or certificate-fingerprint set")?;
+            let tls_name = server.tls_name().unwrap_or(host);
+            let (connection, stream) = quic::connect(host, *port, tls_name, &trust).await?;
+            Connection::Quic(connection, stream)
+        }
+        (ServerAddress::Host { host, port }, Transport::Noise) => {
+            let noise = server.noise.clone().context("The noise transport requires noise to be set")?;
+            if noise.public_key.is_none() && noise.passphrase.is_none() {
+                return Err(anyhow::anyhow!("The noise transport requires public-key or passphrase to be set"));
+            }
+
+            let stream = connect_tcp(&server, host, *port).await?;
+            Connection::Noise(stream, noise)
+        }
+        (ServerAddress::Unix(_), Transport::Noise) | (ServerAddress::Vsock(_), Transport::Noise) => {
+            return Err(anyhow::anyhow!("Noise requires a host and port as the server address"))
+        }
+        (ServerAddress::Unix(_), Transport::Quic) | (ServerAddress::Vsock(_), Transport::Quic) => {
+            return Err(anyhow::anyhow!("QUIC requires a host and port as the server address"))
+        }
+        (ServerAddress::Unix(_), Transport::WebSocket) | (ServerAddress::Vsock(_), Transport::WebSocket) => {
+            return Err(anyhow::anyhow!("WebSocket requires a host and port as the server address"))
+        }
+        #[cfg(unix)]
+        (ServerAddress::Unix(path), Transport::Tcp) => {
+            let stream = UnixStream::connect(path).await?;
+            Connection::Local(Box::new(stream), trust)
+        }
+        #[cfg(not(unix))]
+        (ServerAddress::Unix(_), Transport::Tcp) => {
+            return Err(anyhow::anyhow!("Unix sockets are not supported on this platform"))
+        }
+        #[cfg(target_os = "linux")]
+        (ServerAddress::Vsock(address), Transport::Tcp) => {
+            let stream = VsockStream::connect(*address).await?;
+            Connection::Local(Box::new(stream), trust)
+        }
+        #[cfg(not(target_os = "linux"))]
+        (ServerAddress::Vsock(_), Transport::Tcp) => {
+            return Err(anyhow::anyhow!("vsock is not supported on this platform"))
+        }
+    };
+
+    Ok((name, server, connection))
+}
+
+async fn connect_tcp(server: &Server, host: &str, port: u16) -> Result<TcpStream, Error> {
+    match &server.proxy {
+        Some(proxy) => {
+            log::debug!("Connecting through {}", proxy);
+            proxy::connect(proxy, host, port).await
+        }
+        None => eyeballs::connect(host, port).await,
+    }
+}
+
+// Only done if enabled in the config, unlike keep alive messages it doesn't need the server's support.
+fn set_tcp_keep_alive(stream: &TcpStream, keep_alive: Option<KeepAlive>) {
+    if let Some(keep_alive) = keep_alive {
+        if let Err(err) = net::set_tcp_keep_alive(stream, &keep_alive) {
+            log::warn!("setting TCP keepalive failed: {}", err);
+        }
+    }
+}
+
+// Errors such as mismatched protocol versions or rejected certificates aren't going to go away
+// by connecting again.
+fn is_transient(err: &Error) -> bool {
+    match err.downcast_ref::<net::Error>() {
+        Some(err) => err.is_transient(),
+        None => true,
+    }
 }
 
 async fn run(mut config: Config) -> Result<Infallible, Error> {
-    let (name, server, certificate, stream) = {
-        let (res, _num, _vec) = select_all(config.drain().map(|(name, srv)| {
+    let keep_alive = config
+        .keep_alive
+        .to_net()
+        .context("keep-alive timeout-ms has to be at least twice interval-ms")?;
+    let tcp_keep_alive = Some(keep_alive).filter(|_| config.keep_alive.tcp);
+
+    if let Some(address) = config.listen_address {
+        return listen(&config, address, keep_alive, tcp_keep_alive).await;
+    }
+
+    let (name, server, connection) = {
+        let (res, _num, _vec) = select_all(config.servers.drain().map(|(name, srv)| {
             try_connect(name.to_string(), srv).boxed()
         })).await;
         res?
     };
 
-    let (host, port) = (&server.server_address.host, server.server_address.port);
+    let identity = tls::read_identity(&config).await?;
+    // Servers listing clients by key need to be told ours.
+    let noise_key = match &config.noise_private_key {
+        Some(key) => {
+            log::info!("Noise public key: {}", key.public_key());
+            key.clone()
+        }
+        None => PrivateKey::generate().context("Failed to generate Noise key")?,
+    };
+
+    let mut state = State::default();
+    let mut opened = open(
+        &name,
+        &server,
+        connection,
+        identity.as_ref(),
+        &noise_key,
+        tcp_keep_alive,
+    )
+    .await?;
+    loop {
+        let (stream, peer, quic) = opened;
+        log::info!("Connected to {} ({})", name, server.server_address);
+
+        let err = match session(stream, peer, quic, &config, keep_alive, &mut state).await {
+            Ok(never) => match never {},
+            Err(err) => err,
+        };
+
+        // Only sessions the server gave us a ticket for can be resumed.
+        if state.ticket.is_none() || !is_transient(&err) {
+            return Err(err);
+        }
+
+        log::warn!("Connection to {} lost ({:#}), trying to resume", name, err);
+        opened = reconnect(
+            &name,
+            &server,
+            identity.as_ref(),
+            &noise_key,
+            tcp_keep_alive,
+        )
+        .await?;
+    }
+}
+
+// Tries to get back to the server while it still keeps our session around.
+async fn reconnect(
+    name: &str,
+    server: &Server,
+    identity: Option<&Identity>,
+    noise_key: &PrivateKey,
+    tcp_keep_alive: Option<KeepAlive>,
+) -> Result<Opened, Error> {
+    let deadline = Instant::now() + net::RESUME_TIMEOUT;
+    loop {
+        time::sleep(RECONNECT_INTERVAL).await;
+
+        let result = async {
+            let (_, _, connection) = try_connect(name.to_owned(), server.clone()).await?;
+            open(name, server, connection, identity, noise_key, tcp_keep_alive).await
+        };
+
+        match result.await {
+            Ok(opened) => return Ok(opened),
+            Err(err) if Instant::now() < deadline && is_transient(&err) => {
+                log::debug!("Reconnecting to {} failed: {:#}", name, err)
+            }
+            Err(err) => return Err(err.context("Failed to resume session")),
+        }
+    }
+}
+
+// Sets up TLS and such on a freshly established connection.
+async fn open(
+    name: &str,
+    server: &Server,
+    connection: Connection,
+    identity: Option<&Identity>,
+    noise_key: &PrivateKey,
+    tcp_keep_alive: Option<KeepAlive>,
+) -> Result<Opened, Error> {
+    let address = &server.server_address;
+
+    // Datagrams are exchanged with the same host, there is none for Unix and vsock sockets.
+    // Through a proxy, the peer is the proxy, so datagrams aren't used either.
+    let proxied = server.proxy.is_some();
+    let opened: Opened = match connection {
+        Connection::Tcp(stream, trust) => {
+            log::debug!("Connection open to {} ({}), setting up TLS", name, address);
+
+            if let Err(err) = stream.set_nodelay(true) {
+                log::warn!("setting TCP_NODELAY failed: {}", err);
+            };
+
+            set_tcp_keep_alive(&stream, tcp_keep_alive);
+
+            let peer = if proxied { None } else { Some(stream.peer_addr()?) };
+
+            let stream = BufReader::new(stream);
+            let stream = tls::connect(stream, &trust, identity, server).await?;
+
+            (Box::new(stream), peer, None)
+        }
+        Connection::WebSocket(stream, trust, url) => {
+            log::debug!("Connection open to {} ({}), setting up TLS", name, address);
+
+            if let Err(err) = stream.set_nodelay(true) {
+                log::warn!("setting TCP_NODELAY failed: {}", err);
+            };
+
+            set_tcp_keep_alive(&stream, tcp_keep_alive);
+
+            let peer = if proxied { None } else { Some(stream.peer_addr()?) };
+
+            let stream = tls::connect(stream, &trust, identity, server).await?;
+            let stream = WebSocket::connect(stream, &url)
+                .await
+                .context("WebSocket upgrade failed")?;
+
+            (Box::new(stream), peer, None)
+        }
+        #[cfg(unix)]
+        Connection::Local(stream, Some(trust)) => {
+            log::debug!("Connection open to {} ({}), setting up TLS", name, address);
+
+            let stream = tls::connect(stream, &trust, identity, server).await?;
+            (Box::new(stream), None, None)
+        }
+        #[cfg(unix)]
+        Connection::Local(stream, None) => (stream, None, None),
+        Connection::Quic(connection, stream) => {
+            let peer = connection.remote_address();
+            (Box::new(stream), Some(peer), Some(connection))
+        }
+        Connection::Noise(stream, noise) => {
+            log::debug!("Connection open to {} ({}), setting up Noise", name, address);
+
+            if let Err(err) = stream.set_nodelay(true) {
+                log::warn!("setting TCP_NODELAY failed: {}", err);
+            };
+
+            set_tcp_keep_alive(&stream, tcp_keep_alive);
 
-    log::debug!("Connection open to {} ({}:{}), setting up TLS: {} ", name, host, port,
-        server.certificate_path.to_string_lossy());
+            let peer = if proxied { None } else { Some(stream.peer_addr()?) };
 
-    let connector: tokio_native_tls::TlsConnector = TlsConnector::builder()
-        .add_root_certificate(certificate)
-        .build()
-        .context("Failed to create connector")?
-        .into();
+            let stream = Noise::connect(
+                BufReader::new(stream),
+                noise_key,
+                noise.passphrase.as_ref(),
+                noise.public_key.as_ref(),
+            )
+            .await
+            .context("Noise handshake failed")?;
 
-    if let Err(err) = stream.set_nodelay(true) {
-        log::warn!("setting TCP_NODELAY failed: {}", err);
+            (Box::new(stream), peer, None)
+        }
     };
 
-    let stream = BufReader::new(stream);
-    let mut stream = connector
-        .connect(&server.server_address.host, stream)
+    Ok(opened)
+}
+
+// In reverse-connect mode, servers connect to us, one at a time. Unlike in the normal mode,
+// a lost connection isn't fatal, as the server is going to dial us again.
+async fn listen(
+    config: &Config,
+    address: SocketAddr,
+    keep_alive: KeepAlive,
+    tcp_keep_alive: Option<KeepAlive>,
+) -> Result<Infallible, Error> {
+    let key_pair = tls::read_key_pair(config)
+        .await?
+        .context("listen-address requires identity-path or identity-certificate-path and identity-key-path to be set")?;
+    let trust = Trust::read(config.certificate_path.as_deref(), config.certificate_fingerprint)
+        .await?
+        .context("listen-address requires certificate-path or certificate-fingerprint to be set")?;
+    let acceptor = tls::acceptor(key_pair, &trust)?;
+
+    let listener = TcpListener::bind(address).await?;
+    log::info!("Listening on {}", address);
+
+    // Servers dial us again right away, so sessions are resumed just the same.
+    let mut state = State::default();
+    // Certificate of the server the ticket was issued by.
+    let mut issuer = None;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        if let Err(err) = stream.set_nodelay(true) {
+            log::warn!("setting TCP_NODELAY failed: {}", err);
+        };
+
+        set_tcp_keep_alive(&stream, tcp_keep_alive);
+
+        // Otherwise anyone opening a connection without completing the handshake would keep the server out.
+        let accept = acceptor.accept(BufReader::new(stream));
+        let stream = match time::timeout(keep_alive.timeout(), accept).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(err)) => {
+                log::error!("{}: TLS error: {}", peer, err);
+                continue;
+            }
+            Err(_) => {
+                log::error!("{}: TLS handshake timed out", peer);
+                continue;
+            }
+        };
+
+        // The acceptor only lets in servers presenting the configured certificate (or one issued by it),
+        // and the ticket is only handed to the server it came from.
+        let certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| certificate.0.clone());
+        if certificate.is_none() || certificate != issuer {
+            state.ticket = None;
+            issuer = certificate;
+        }
+
+        log::info!("Connected to {}", peer);
+
+        let err = match session(
+            Box::new(stream),
+            Some(peer),
+            None,
+            config,
+            keep_alive,
+            &mut state,
+        )
         .await
-        .context("Failed to connect")?;
+        {
+            Ok(never) => match never {},
+            Err(err) => err,
+        };
+        log::error!("{}: disconnected ({:#})", peer, err);
+    }
+}
 
-    log::info!("Connected to {} ({}:{})", name, host, port);
+async fn session(
+    mut stream: Box<dyn Stream>,
+    peer: Option<SocketAddr>,
+    quic: Option<quinn::Connection>,
+    config: &Config,
+    keep_alive: KeepAlive,
+    state: &mut State,
+) -> Result<Infallible, Error> {
+    let handshake = net::handshake(
+        &mut stream,
+        Role::Client,
+        Capabilities::SUPPORTED,
+        keep_alive,
+    )
+    .await?;
+    let max_frame_size = config.max_frame_size.unwrap_or(net::DEFAULT_MAX_FRAME_SIZE);
+    let framing = handshake.framing.max_frame_size(max_frame_size);
+    let keep_alive = handshake.keep_alive;
+    // Anything written has to make it within the negotiated timeout, just like keep alives.
+    let timeout = keep_alive.timeout();
+    log::debug!(
+        "Using protocol version {} with capabilities {:?} and {:?}",
+        handshake.version,
+        handshake.capabilities,
+        keep_alive
+    );
 
-    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
+    if handshake.has_hello() {
+        let hostname = hostname::get()
+            .context("Failed to get hostname")?
+            .to_string_lossy()
+            .into_owned();
+        let hello = Hello {
+            name: config.name.clone().unwrap_or_else(|| hostname.clone()),
+            hostname,
+            os: env::consts::OS.to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            screen: config.screen,
+        };
+
+        log::debug!("Introducing ourselves as {}", hello.name);
+        send(&mut stream, &Message::Hello(hello), &framing, timeout).await?;
+    }
 
-    let version = net::read_version(&mut stream).await?;
-    if version != PROTOCOL_VERSION {
-        return Err(anyhow::anyhow!(
-            "Incompatible protocol version (got {}, expecting {})",
-            version,
-            PROTOCOL_VERSION
-        ));
+    if handshake.has_resume() {
+        let resume = state.ticket.map(|ticket| Resume {
+            ticket,
+            received: state.received,
+        });
+        send(&mut stream, &Message::Resume(resume), &framing, timeout).await?;
+    } else {
+        state.ticket = None;
     }
 
-    let mut writer = EventWriter::new().await?;
+    let reports = handshake.capabilities.contains(Capabilities::REPORTS);
+    let writer = match &mut state.writer {
+        Some(writer) => writer,
+        None => match EventWriter::new().await {
+            Ok(writer) => state.writer.insert(writer),
+            Err(err) => {
+                let err = Error::new(err).context("Failed to create event writer");
+                return Err(report_error(&mut stream, &framing, timeout, reports, err).await);
+            }
+        },
+    };
+
+    if reports {
+        let report = Report::Status(format!("Ready to inject events ({})", env::consts::OS));
+        send(&mut stream, &Message::Report(report), &framing, timeout).await?;
+    }
+
+    // Reading messages is not cancel safe, so it's done in a separate task.
+    let (mut reader, mut stream) = tokio_io::split(stream);
+    let (message_sender, mut messages) = mpsc::channel(1);
+
+    // With QUIC, mouse movement arrives on a stream opened by the server, its messages are handled like any other.
+    if let Some(connection) = quic {
+        let message_sender = message_sender.clone();
+        tokio::spawn(async move {
+            let mut motion = match connection.accept_uni().await {
+                Ok(motion) => motion,
+                Err(err) => {
+                    let _ = message_sender.send(Err(io::Error::from(err).into())).await;
+                    return;
+                }
+            };
+
+            loop {
+                let message = net::read_message(&mut motion, &framing).await;
+                if message_sender.send(message).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let message = time::timeout(keep_alive.timeout(), net::read_message(&mut reader, &framing))
+                .await
+                .unwrap_or(Err(net::Error::Timeout));
+            if message_sender.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let (frame_sender, mut frames) = mpsc::channel(1);
+    // The last sequenced frame acknowledged on this connection.
+    let mut acked = 0;
+
     loop {
-        let message = time::timeout(net::MESSAGE_TIMEOUT, net::read_message(&mut stream))
-            .await
-            .context("Read timed out")??;
-        match message {
-            Message::Event(event) => writer.write(event).await?,
-            Message::KeepAlive => {}
+        let message = tokio::select! {
+            message = messages.recv() => message.unwrap(),
+            // Frames received over UDP are handled just like those received over TCP.
+            frame = frames.recv() => Ok(Message::Frame(frame.unwrap())),
+        };
+        let message = match message {
+            Ok(message) => message,
+            // The server knows about a key we don't, skip the message and carry on.
+            Err(net::Error::MalformedFrame(err)) if err.is::<UnknownCode>() => {
+                log::warn!("Ignoring message: {}", err);
+
+                if reports {
+                    let report = Report::InjectionFailed(err.to_string());
+                    send(&mut stream, &Message::Report(report), &framing, timeout).await?;
+                }
+
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let frame = match message {
+            Message::Event(event) => vec![event],
+            Message::Frame(events) => events,
+            Message::SequencedFrame(sequence, events) => {
+                // Replayed, but received before the connection was lost after all.
+                if sequence <= state.received {
+                    continue;
+                }
+
+                state.received = sequence;
+                events
+            }
+            Message::Session(info) => {
+                // A new session numbers its frames from scratch.
+                if state.ticket != Some(info.ticket) {
+                    state.received = 0;
+                    acked = 0;
+                }
+
+                state.ticket = Some(info.ticket);
+
+                // Keys released while we were gone, or all of them if the session couldn't be resumed.
+                let pressed: HashSet<_> = info.pressed.into_iter().collect();
+                let release: Vec<_> = state
+                    .pressed
+                    .difference(&pressed)
+                    .map(|kind| Event::Key {
+                        direction: Direction::Up,
+                        kind: *kind,
+                    })
+                    .collect();
+
+                if release.is_empty() {
+                    continue;
+                }
+
+                log::debug!("Releasing {} keys and buttons", release.len());
+                release
+            }
+            Message::Ping(timestamp) => {
+                // Piggybacked on pings, so that the server can forget frames we are certain to have.
+                if state.received > acked {
+                    let ack = Message::Ack(state.received);
+                    send(&mut stream, &ack, &framing, timeout).await?;
+                    acked = state.received;
+                }
+
+                send(&mut stream, &Message::Pong(timestamp), &framing, timeout).await?;
+                continue;
+            }
+            Message::KeepAlive => continue,
+            Message::Datagram(setup) => {
+                // Frames keep coming over the connection as long as the server doesn't hear from us over UDP.
+                let peer = match peer {
+                    Some(peer) => peer,
+                    None => {
+                        log::debug!("Ignoring datagram setup, the server can't be reached directly");
+                        continue;
+                    }
+                };
+                let address: SocketAddr = if peer.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+
+                let socket = UdpSocket::bind(address).await?;
+                socket.connect((peer.ip(), setup.port)).await?;
+
+                log::debug!("Receiving mouse motion over UDP port {}", setup.port);
+                tokio::spawn(receive_datagrams(
+                    socket,
+                    setup,
+                    keep_alive.interval(),
+                    frame_sender.clone(),
+                ));
+                continue;
+            }
+            Message::Rejected(reason) => {
+                state.ticket = None;
+                return Err(anyhow::anyhow!("Rejected by server: {}", reason));
+            }
+            message @ Message::Hello(_)
+            | message @ Message::Pong(_)
+            | message @ Message::Report(_)
+            | message @ Message::Ack(_)
+            | message @ Message::Resume(_) => {
+                state.ticket = None;
+                return Err(anyhow::anyhow!("Unexpected message {:?}", message));
+            }
+        };
+
+        if let Err(err) = writer.write_frame(&frame).await {
+            state.ticket = None;
+            let err = Error::new(err).context("Failed to write events");
+            return Err(report_error(&mut stream, &framing, timeout, reports, err).await);
+        }
+
+        for event in &frame {
+            if let Event::Key { direction, kind } = *event {
+                match direction {
+                    Direction::Down => state.pressed.insert(kind),
+                    Direction::Up => state.pressed.remove(&kind),
+                };
+            }
         }
     }
 }
 
+async fn send<S>(
+    stream: &mut S,
+    message: &Message,
+    framing: &Framing,
+    timeout: Duration,
+) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    time::timeout(timeout, net::write_message(stream, message, framing))
+    .await
+    .context("Write timed out")??;
+
+    Ok(())
+}
+
+// Receives frames sent over UDP, sending empty datagrams to let the server know where we are
+// and to keep NAT mappings alive.
+async fn receive_datagrams(
+    socket: UdpSocket,
+    setup: DatagramSetup,
+    interval: Duration,
+    frames: mpsc::Sender<Vec<Event>>,
+) {
+    let mut sealer = DatagramSealer::new(&setup, DatagramDirection::ClientToServer);
+    let mut opener = DatagramOpener::new(&setup, DatagramDirection::ServerToClient);
+    let mut keep_alive = time::interval(interval);
+    let mut buffer = [0; 2048];
+
+    loop {
+        tokio::select! {
+            _ = keep_alive.tick() => {
+                if frames.is_closed() {
+                    return;
+                }
+
+                let result = match sealer.seal(&[]) {
+                    Ok(datagram) => socket.send(&datagram).await.map(drop),
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = result {
+                    log::debug!("Sending datagram failed: {}", err);
+                }
+            }
+            length = socket.recv(&mut buffer) => {
+                let length = match length {
+                    Ok(length) => length,
+                    Err(err) => {
+                        log::debug!("Receiving datagram failed: {}", err);
+                        continue;
+                    }
+                };
+
+                match opener.open(&buffer[..length]) {
+                    Ok(Some(frame)) => {
+                        if frames.send(frame).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => log::debug!("Dropping datagram: {}", err),
+                }
+            }
+        }
+    }
+}
+
+// Lets the server know why we're about to disconnect, if it supports reports.
+async fn report_error<S>(
+    stream: &mut S,
+    framing: &Framing,
+    timeout: Duration,
+    reports: bool,
+    err: Error,
+) -> Error
+where
+    S: AsyncWrite + Unpin,
+{
+    if reports {
+        let report = Message::Report(Report::Error(format!("{:#}", err)));
+        // Best effort, we're giving up on the connection anyway.
+        let _ = send(stream, &report, framing, timeout).await;
+    }
+
+    err
+}
+
 #[derive(StructOpt)]
 #[structopt(name = "rkvm-client", about = "The rkvm client application")]
 struct Args {
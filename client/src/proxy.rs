@@ -0,0 +1,83 @@
+use crate::config::{Proxy, ProxyKind};
+use crate::eyeballs;
+use anyhow::{Context, Error};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+// The longest HTTP response head we're willing to read from a proxy.
+const MAX_RESPONSE_LENGTH: usize = 8192;
+
+// Opens a tunnel to host:port through the proxy. The host is resolved by the proxy, not by us.
+pub async fn connect(proxy: &Proxy, host: &str, port: u16) -> Result<TcpStream, Error> {
+    let stream = eyeballs::connect(&proxy.host, proxy.port)
+        .await
+        .context("Failed to connect to proxy")?;
+
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5(stream, proxy, host, port).await,
+        ProxyKind::Http => http(stream, proxy, host, port).await,
+    }
+}
+
+async fn socks5(
+    stream: TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, Error> {
+    let stream = match &proxy.credentials {
+        Some((username, password)) => {
+            Socks5Stream::connect_with_password_and_socket(stream, (host, port), username, password)
+                .await
+        }
+        None => Socks5Stream::connect_with_socket(stream, (host, port)).await,
+    };
+
+    stream
+        .map(Socks5Stream::into_inner)
+        .context("SOCKS5 proxy error")
+}
+
+async fn http(
+    mut stream: TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, Error> {
+    let target = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((username, password)) = &proxy.credentials {
+        let credentials = base64::encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read one byte at a time, so that nothing past the response head is consumed.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() == MAX_RESPONSE_LENGTH {
+            return Err(anyhow::anyhow!("HTTP proxy response too long"));
+        }
+
+        let byte = stream
+            .read_u8()
+            .await
+            .context("HTTP proxy closed the connection")?;
+        response.push(byte);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') => Ok(stream),
+        _ => Err(anyhow::anyhow!("HTTP proxy refused to connect: {}", status)),
+    }
+}
@@ -0,0 +1,54 @@
+use crate::tls::{self, FingerprintVerifier, Trust};
+use anyhow::{Context, Error};
+use net::Duplex;
+use quinn::{ClientConfig, Connection, Endpoint, RecvStream, SendStream};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::lookup_host;
+
+pub async fn connect(
+    host: &str,
+    port: u16,
+    tls_name: &str,
+    trust: &Trust,
+) -> Result<(Connection, Duplex<RecvStream, SendStream>), Error> {
+    let config = match trust {
+        Trust::Certificate(certificate) => {
+            ClientConfig::with_root_certificates(tls::root_store(certificate)?)
+        }
+        Trust::Fingerprint(fingerprint) => {
+            // The same as ClientConfig::with_root_certificates sets up, QUIC requires TLS 1.3.
+            let mut config = rustls::ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .context("Failed to create QUIC config")?
+                .with_custom_certificate_verifier(Arc::new(FingerprintVerifier(*fingerprint)))
+                .with_no_client_auth();
+            config.enable_early_data = true;
+
+            ClientConfig::new(Arc::new(config))
+        }
+    };
+
+    let address = lookup_host((host, port))
+        .await?
+        .next()
+        .context("Failed to resolve server address")?;
+    let local: SocketAddr = if address.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+
+    let endpoint = Endpoint::client(local)?;
+    let connection = endpoint
+        .connect_with(config, address, tls_name)?
+        .await
+        .context("Failed to connect")?;
+
+    // Everything but mouse movement goes over this stream, the server opens another one for that.
+    let (send, receive) = connection.open_bi().await?;
+
+    Ok((connection, Duplex::new(receive, send)))
+}
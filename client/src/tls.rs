@@ -0,0 +1,400 @@
+use crate::config::{Config, Server};
+use anyhow::{Context, Error};
+use net::Fingerprint;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::server::{AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, DistinguishedName, PrivateKey, RootCertStore, ServerConfig, ServerName};
+use rustls_pemfile::Item;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "rustls")]
+use {rustls::client::WebPkiVerifier, rustls::ClientConfig, std::convert::TryFrom};
+#[cfg(not(feature = "rustls"))]
+use {
+    std::io,
+    tokio_native_tls::native_tls::{self, TlsConnector},
+};
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Either the native-tls or the rustls feature has to be enabled");
+
+// Any name does when only the fingerprint is checked, it's only sent along for SNI then.
+const ANY_NAME: &str = "rkvm";
+
+#[cfg(not(feature = "rustls"))]
+pub type Identity = native_tls::Identity;
+
+#[cfg(feature = "rustls")]
+pub type Identity = KeyPair;
+
+// The reverse-connect listener always uses rustls, as native-tls can't require client certificates.
+#[derive(Clone)]
+pub struct KeyPair {
+    certificates: Vec<Certificate>,
+    key: PrivateKey,
+}
+
+#[cfg(not(feature = "rustls"))]
+pub type TlsStream<S> = tokio_native_tls::TlsStream<S>;
+#[cfg(feature = "rustls")]
+pub type TlsStream<S> = tokio_rustls::client::TlsStream<S>;
+
+// How the certificate of a server is checked.
+#[derive(Clone)]
+pub enum Trust {
+    // A PEM or DER file, the certificate is checked against the server name as well.
+    Certificate(Vec<u8>),
+    // Only the fingerprint is compared, regardless of names and expiry.
+    Fingerprint(Fingerprint),
+}
+
+impl Trust {
+    // None if neither certificate-path nor certificate-fingerprint is set.
+    pub async fn load(server: &Server) -> Result<Option<Self>, Error> {
+        Self::read(
+            server.certificate_path.as_deref(),
+            server.certificate_fingerprint,
+        )
+        .await
+    }
+
+    // The same, but for the top-level options checking servers which connect to us.
+    pub async fn read(
+        path: Option<&Path>,
+        fingerprint: Option<Fingerprint>,
+    ) -> Result<Option<Self>, Error> {
+        let trust = match (path, fingerprint) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "Only one of certificate-path and certificate-fingerprint can be set"
+                ))
+            }
+            (Some(path), None) => {
+                let certificate = fs::read(path).await.context("Failed to read certificate")?;
+                Some(Self::Certificate(certificate))
+            }
+            (None, Some(fingerprint)) => Some(Self::Fingerprint(fingerprint)),
+            (None, None) => None,
+        };
+
+        Ok(trust)
+    }
+}
+
+// Tells what the server presented instead, so that the config can be updated if the certificate was replaced on purpose.
+pub fn check_fingerprint(expected: &Fingerprint, certificate: &[u8]) -> bool {
+    let presented = match Fingerprint::of(certificate) {
+        Some(presented) => presented,
+        None => {
+            log::error!("Failed to parse the server certificate");
+            return false;
+        }
+    };
+    if presented != *expected {
+        log::error!(
+            "Server certificate fingerprint mismatch, expected {} but the server presented {}",
+            expected,
+            presented
+        );
+
+        return false;
+    }
+
+    true
+}
+
+pub struct FingerprintVerifier(pub Fingerprint);
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !check_fingerprint(&self.0, &end_entity.0) {
+            return Err(rustls::Error::General(
+                "Server certificate fingerprint mismatch".to_owned(),
+            ));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// In reverse-connect mode, it's the server presenting a client certificate.
+impl ClientCertVerifier for FingerprintVerifier {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        if !check_fingerprint(&self.0, &end_entity.0) {
+            return Err(rustls::Error::General(
+                "Server certificate fingerprint mismatch".to_owned(),
+            ));
+        }
+
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+// Accepts both PEM and DER.
+pub fn root_store(certificate: &[u8]) -> Result<RootCertStore, Error> {
+    let mut certificates =
+        rustls_pemfile::certs(&mut &*certificate).context("Failed to parse certificate")?;
+    // Not a PEM file, presumably DER then.
+    if certificates.is_empty() {
+        certificates.push(certificate.to_vec());
+    }
+
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates {
+        roots
+            .add(&Certificate(certificate))
+            .context("Failed to parse certificate")?;
+    }
+
+    Ok(roots)
+}
+
+// identity-certificate-path and identity-key-path if set, identity-path otherwise.
+pub async fn read_identity(config: &Config) -> Result<Option<Identity>, Error> {
+    read(config, parse_pem_identity, parse_pkcs12_identity).await
+}
+
+// The same identity, but for the reverse-connect listener.
+pub async fn read_key_pair(config: &Config) -> Result<Option<KeyPair>, Error> {
+    read(config, parse_pem_key_pair, parse_pkcs12_key_pair).await
+}
+
+async fn read<T>(
+    config: &Config,
+    parse_pem: fn(&[u8], &[u8]) -> Result<T, Error>,
+    parse_pkcs12: fn(&[u8], &str) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
+    if let (Some(certificate_path), Some(key_path)) =
+        (&config.identity_certificate_path, &config.identity_key_path)
+    {
+        let certificate = fs::read(certificate_path)
+            .await
+            .context("Failed to read identity certificate")?;
+        let key = fs::read(key_path)
+            .await
+            .context("Failed to read identity key")?;
+
+        return parse_pem(&certificate, &key)
+            .context("Failed to parse identity certificate and key")
+            .map(Some);
+    }
+
+    let identity_path = match &config.identity_path {
+        Some(identity_path) => identity_path,
+        None => return Ok(None),
+    };
+
+    let identity = fs::read(identity_path)
+        .await
+        .context("Failed to read identity")?;
+    let identity =
+        parse_pkcs12(&identity, &config.identity_password).context("Failed to parse identity")?;
+
+    Ok(Some(identity))
+}
+
+// Only PKCS#8 keys are supported, as written by OpenSSL 1.1 and later.
+#[cfg(not(feature = "rustls"))]
+fn parse_pem_identity(certificate: &[u8], key: &[u8]) -> Result<Identity, Error> {
+    Ok(Identity::from_pkcs8(certificate, key)?)
+}
+
+#[cfg(feature = "rustls")]
+fn parse_pem_identity(certificate: &[u8], key: &[u8]) -> Result<Identity, Error> {
+    parse_pem_key_pair(certificate, key)
+}
+
+fn parse_pem_key_pair(certificate: &[u8], key: &[u8]) -> Result<KeyPair, Error> {
+    let certificates = rustls_pemfile::certs(&mut &*certificate)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::read_all(&mut &*key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .context("No private key found")?;
+
+    Ok(KeyPair { certificates, key })
+}
+
+#[cfg(not(feature = "rustls"))]
+fn parse_pkcs12_identity(identity: &[u8], password: &str) -> Result<Identity, Error> {
+    Ok(Identity::from_pkcs12(identity, password)?)
+}
+
+#[cfg(feature = "rustls")]
+fn parse_pkcs12_identity(identity: &[u8], password: &str) -> Result<Identity, Error> {
+    parse_pkcs12_key_pair(identity, password)
+}
+
+fn parse_pkcs12_key_pair(identity: &[u8], password: &str) -> Result<KeyPair, Error> {
+    let keystore = p12_keystore::KeyStore::from_pkcs12(identity, password)?;
+    let (_, chain) = keystore
+        .private_key_chain()
+        .context("No private key found")?;
+
+    let certificates = chain
+        .chain()
+        .iter()
+        .map(|certificate| Certificate(certificate.as_der().to_vec()))
+        .collect();
+
+    Ok(KeyPair {
+        certificates,
+        key: PrivateKey(chain.key().to_vec()),
+    })
+}
+
+#[cfg(not(feature = "rustls"))]
+pub async fn connect<S>(
+    stream: S,
+    trust: &Trust,
+    identity: Option<&Identity>,
+    server: &Server,
+) -> Result<TlsStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut builder = TlsConnector::builder();
+    let tls_name = match trust {
+        Trust::Certificate(certificate) => {
+            let certificate = native_tls::Certificate::from_der(certificate)
+                .or_else(|_| native_tls::Certificate::from_pem(certificate))
+                .context("Failed to parse certificate")?;
+            builder.add_root_certificate(certificate);
+
+            tls_name(server)?
+        }
+        Trust::Fingerprint(_) => {
+            // Checked below once the handshake is done, which proves that the server has the key.
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+
+            server.tls_name().unwrap_or(ANY_NAME)
+        }
+    };
+
+    if let Some(identity) = identity {
+        builder.identity(identity.clone());
+    }
+
+    let connector: tokio_native_tls::TlsConnector = builder
+        .build()
+        .context("Failed to create connector")?
+        .into();
+
+    let stream = connector
+        .connect(tls_name, stream)
+        .await
+        .map_err(net::Error::tls)
+        .context("Failed to connect")?;
+
+    if let Trust::Fingerprint(fingerprint) = trust {
+        let certificate = stream
+            .get_ref()
+            .peer_certificate()
+            .and_then(|certificate| {
+                certificate
+                    .map(|certificate| certificate.to_der())
+                    .transpose()
+            })
+            .map_err(|err| net::Error::Tls(err.into()))?
+            .context("The server presented no certificate")?;
+
+        if !check_fingerprint(fingerprint, &certificate) {
+            let err = io::Error::other("Server certificate fingerprint mismatch");
+            return Err(net::Error::Tls(err.into()).into());
+        }
+    }
+
+    Ok(stream)
+}
+
+#[cfg(feature = "rustls")]
+pub async fn connect<S>(
+    stream: S,
+    trust: &Trust,
+    identity: Option<&Identity>,
+    server: &Server,
+) -> Result<TlsStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (verifier, tls_name): (Arc<dyn ServerCertVerifier>, _) = match trust {
+        Trust::Certificate(certificate) => {
+            let verifier = WebPkiVerifier::new(root_store(certificate)?, None);
+            (Arc::new(verifier), tls_name(server)?)
+        }
+        Trust::Fingerprint(fingerprint) => (
+            Arc::new(FingerprintVerifier(*fingerprint)),
+            server.tls_name().unwrap_or(ANY_NAME),
+        ),
+    };
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+    let config = match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(identity.certificates.clone(), identity.key.clone())
+            .context("Failed to create connector")?,
+        None => builder.with_no_client_auth(),
+    };
+
+    let tls_name = ServerName::try_from(tls_name).context("Invalid server name")?;
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+
+    connector
+        .connect(tls_name, stream)
+        .await
+        .map_err(net::Error::tls)
+        .context("Failed to connect")
+}
+
+// Used in reverse-connect mode, the server has to present a certificate matching the trust.
+pub fn acceptor(key_pair: KeyPair, trust: &Trust) -> Result<tokio_rustls::TlsAcceptor, Error> {
+    let verifier: Arc<dyn ClientCertVerifier> = match trust {
+        Trust::Certificate(certificate) => {
+            Arc::new(AllowAnyAuthenticatedClient::new(root_store(certificate)?))
+        }
+        Trust::Fingerprint(fingerprint) => Arc::new(FingerprintVerifier(*fingerprint)),
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(key_pair.certificates, key_pair.key)
+        .context("Failed to create TLS acceptor")?;
+
+    Ok(Arc::new(config).into())
+}
+
+fn tls_name(server: &Server) -> Result<&str, Error> {
+    server
+        .tls_name()
+        .context("TLS over Unix and vsock sockets requires server-name to be set")
+}
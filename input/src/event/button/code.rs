@@ -1,10 +1,12 @@
 use crate::event::Button;
 
+// Codes are taken from the Linux input subsystem (input-event-codes.h) regardless of the platform.
+// They are also what goes on the wire, so they must never change.
 impl Button {
-    pub(crate) fn to_raw(&self) -> u16 {
+    pub fn to_code(self) -> u16 {
         use Button::*;
 
-        match *self {
+        match self {
             A => 0x0130,
             B => 0x0131,
             Back => 0x0116,
@@ -121,13 +123,13 @@ impl Button {
             TriggerHappy9 => 0x02C8,
             West => 0x0134,
             Wheel => 0x0150,
-            X => 0x0130,
-            Y => 0x0150,
+            X => 0x0133,
+            Y => 0x0134,
             Z => 0x0135,
         }
     }
 
-    pub(crate) fn from_raw(code: u16) -> Option<Self> {
+    pub fn from_code(code: u16) -> Option<Self> {
         use Button::*;
 
         // This is generated from linux headers, some patterns are unreachable, and we don't care.
@@ -1,10 +1,12 @@
 use crate::event::Key;
 
+// Codes are taken from the Linux input subsystem (input-event-codes.h) regardless of the platform.
+// They are also what goes on the wire, so they must never change.
 impl Key {
-    pub(crate) fn to_raw(&self) -> u16 {
+    pub fn to_code(self) -> u16 {
         use Key::*;
 
-        match *self {
+        match self {
             A => 0x001E,
             Ab => 0x0196,
             AddressBook => 0x01AD,
@@ -496,7 +498,7 @@ impl Key {
         }
     }
 
-    pub(crate) fn from_raw(code: u16) -> Option<Self> {
+    pub fn from_code(code: u16) -> Option<Self> {
         use Key::*;
 
         // This is generated from linux headers, some patterns are unreachable, and we don't care.
@@ -1,6 +1,3 @@
-mod button;
-mod key;
-
 use crate::event::{Axis, Button, Direction, Event, Key, KeyKind, Scroll};
 use crate::linux::glue::{self, input_event, timeval};
 
@@ -79,15 +76,15 @@ impl Event {
 
 impl KeyKind {
     pub(crate) fn from_raw(code: u16) -> Option<KeyKind> {
-        Key::from_raw(code)
+        Key::from_code(code)
             .map(KeyKind::Key)
-            .or_else(|| Button::from_raw(code).map(KeyKind::Button))
+            .or_else(|| Button::from_code(code).map(KeyKind::Button))
     }
 
     pub(crate) fn to_raw(&self) -> u16 {
         match self {
-            KeyKind::Key(key) => key.to_raw(),
-            KeyKind::Button(button) => button.to_raw(),
+            KeyKind::Key(key) => key.to_code(),
+            KeyKind::Button(button) => button.to_code(),
         }
     }
 }
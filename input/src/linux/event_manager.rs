@@ -15,7 +15,7 @@ const EVENT_PATH: &str = "/dev/input";
 
 pub struct EventManager {
     writer: EventWriter,
-    event_receiver: UnboundedReceiver<Result<Event, Error>>,
+    event_receiver: UnboundedReceiver<Result<Vec<Event>, Error>>,
     watcher_receiver: Receiver<Error>,
 }
 
@@ -57,7 +57,8 @@ impl EventManager {
         })
     }
 
-    pub async fn read(&mut self) -> Result<Event, Error> {
+    // Returns a frame of events which happened at the same time, see EventWriter::write_frame.
+    pub async fn read(&mut self) -> Result<Vec<Event>, Error> {
         if let Ok(err) = self.watcher_receiver.try_recv() {
             return Err(err);
         }
@@ -71,11 +72,15 @@ impl EventManager {
     pub async fn write(&mut self, event: Event) -> Result<(), Error> {
         self.writer.write(event).await
     }
+
+    pub async fn write_frame(&mut self, frame: &[Event]) -> Result<(), Error> {
+        self.writer.write_frame(frame).await
+    }
 }
 
 async fn spawn_reader(
     path: &Path,
-    sender: UnboundedSender<Result<Event, Error>>,
+    sender: UnboundedSender<Result<Vec<Event>, Error>>,
 ) -> Result<(), Error> {
     if path.is_dir() {
         return Ok(());
@@ -101,7 +106,7 @@ async fn spawn_reader(
     Ok(())
 }
 
-async fn handle_notify(sender: UnboundedSender<Result<Event, Error>>) -> Result<(), Error> {
+async fn handle_notify(sender: UnboundedSender<Result<Vec<Event>, Error>>) -> Result<(), Error> {
     let mut inotify = Inotify::init()?;
     inotify.add_watch(EVENT_PATH, WatchMask::CREATE)?;
 
@@ -120,10 +125,10 @@ async fn handle_notify(sender: UnboundedSender<Result<Event, Error>>) -> Result<
     Ok(())
 }
 
-async fn handle_events(mut reader: EventReader, sender: UnboundedSender<Result<Event, Error>>) {
+async fn handle_events(mut reader: EventReader, sender: UnboundedSender<Result<Vec<Event>, Error>>) {
     loop {
         let result = match reader.read().await {
-            Ok(event) => sender.send(Ok(event)).is_ok(),
+            Ok(frame) => sender.send(Ok(frame)).is_ok(),
             // This happens if the device is disconnected.
             // In that case simply terminate the reading task.
             Err(ref err) if err.raw_os_error() == Some(libc::ENODEV) => false,
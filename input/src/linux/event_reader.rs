@@ -96,7 +96,9 @@ impl EventReader {
         })
     }
 
-    pub async fn read(&mut self) -> Result<Event, Error> {
+    // Returns all events up to the next SYN_REPORT, so that they can be applied at once.
+    pub async fn read(&mut self) -> Result<Vec<Event>, Error> {
+        let mut frame = Vec::new();
         loop {
             let result = self.file.readable().await?.try_io(|_| {
                 let mut event = MaybeUninit::uninit();
@@ -123,12 +125,14 @@ impl EventReader {
             };
 
             if let Some(event) = Event::from_raw(event) {
-                return Ok(event);
+                frame.push(event);
+                continue;
             }
 
             log::trace!("not understood, putting back: {}/{}/{}", event.type_, event.code, event.value);
 
             // Not understood, write it back.
+            // This includes the SYN_REPORT itself, which also terminates any events we did not understand.
             let ret = unsafe {
                 glue::libevdev_uinput_write_event(
                     self.uinput as *const _,
@@ -141,6 +145,13 @@ impl EventReader {
             if ret < 0 {
                 return Err(Error::from_raw_os_error(-ret));
             }
+
+            if event.type_ as u32 == glue::EV_SYN
+                && event.code as u32 == glue::SYN_REPORT
+                && !frame.is_empty()
+            {
+                return Ok(frame);
+            }
         }
     }
 }
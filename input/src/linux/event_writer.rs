@@ -1,6 +1,6 @@
 use crate::event::{Event, KeyKind};
 use crate::linux::device_id;
-use crate::linux::glue::{self, input_event, libevdev, libevdev_uinput};
+use crate::linux::glue::{self, libevdev, libevdev_uinput};
 use std::io::{Error, ErrorKind};
 use std::mem::MaybeUninit;
 use std::ops::RangeInclusive;
@@ -53,32 +53,31 @@ impl Device {
         Ok(Self { evdev, uinput })
     }
 
-    pub async fn write(&mut self, event: Event) -> Result<(), Error> {
-        self.write_raw(event.to_raw())
+    pub async fn write(&mut self, frame: &[Event]) -> Result<(), Error> {
+        for event in frame {
+            let event = event.to_raw();
+            self.write_raw(event.type_, event.code, event.value)?;
+        }
+
+        // A single EV_SYN per frame makes the events appear to happen at once, e.g. a diagonal mouse move.
+        self.write_raw(glue::EV_SYN as _, glue::SYN_REPORT as _, 0)
     }
 
-    fn write_raw(&mut self, event: input_event) -> Result<(), Error> {
+    fn write_raw(&mut self, r#type: u16, code: u16, value: i32) -> Result<(), Error> {
         // As far as tokio is concerned, the FD never becomes ready for writing, so just write it normally.
         // If an error happens, it will be propagated to caller and the FD is opened in nonblocking mode anyway,
         // so it shouldn't be an issue.
-        let events = [
-            (event.type_, event.code, event.value),
-            (glue::EV_SYN as _, glue::SYN_REPORT as _, 0), // Include EV_SYN.
-        ];
-
-        for (r#type, code, value) in events.iter().cloned() {
-            let ret = unsafe {
-                glue::libevdev_uinput_write_event(
-                    self.uinput as *const _,
-                    r#type as _,
-                    code as _,
-                    value,
-                )
-            };
+        let ret = unsafe {
+            glue::libevdev_uinput_write_event(
+                self.uinput as *const _,
+                r#type as _,
+                code as _,
+                value,
+            )
+        };
 
-            if ret < 0 {
-                return Err(Error::from_raw_os_error(-ret));
-            }
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
         }
 
         Ok(())
@@ -148,8 +147,8 @@ impl Mouse {
         Ok(Self { device })
     }
 
-    pub async fn write(&mut self, event: Event) -> Result<(), Error> {
-        self.device.write(event).await?;
+    pub async fn write(&mut self, frame: &[Event]) -> Result<(), Error> {
+        self.device.write(frame).await?;
         Ok(())
     }
 }
@@ -169,8 +168,8 @@ impl Keyboard {
         Ok(Self { device })
     }
 
-    pub async fn write(&mut self, event: Event) -> Result<(), Error> {
-        self.device.write(event).await?;
+    pub async fn write(&mut self, frame: &[Event]) -> Result<(), Error> {
+        self.device.write(frame).await?;
         Ok(())
     }
 }
@@ -188,23 +187,34 @@ impl EventWriter {
     }
 
     pub async fn write(&mut self, event: Event) -> Result<(), Error> {
-        let dev_type = match event {
-            Event::MouseScroll { delta:_, scroll:_ } => DevType::Mouse,
-            Event::MouseMove { axis:_, delta:_ }     => DevType::Mouse,
-            Event::Key { direction:_, kind } => match kind {
-                  KeyKind::Button(_) => DevType::Mouse,
-                  _                  => DevType::Keyboard,
-            },
-        };
-        match dev_type {
-            DevType::Mouse => {
-                log::debug!("mouse <= {:?}", event);
-                self.mouse.write(event).await
-            },
-            DevType::Keyboard => {
-                log::debug!("keyboard <= {:?}", event);
-                self.keyboard.write(event).await
-            }
+        self.write_frame(&[event]).await
+    }
+
+    // Writes events which belong together, each device gets a single EV_SYN at the end.
+    pub async fn write_frame(&mut self, frame: &[Event]) -> Result<(), Error> {
+        let (mouse, keyboard): (Vec<_>, Vec<_>) = frame.iter().partition(|event| {
+            let dev_type = match event {
+                Event::MouseScroll { delta:_, scroll:_ } => DevType::Mouse,
+                Event::MouseMove { axis:_, delta:_ }     => DevType::Mouse,
+                Event::Key { direction:_, kind } => match kind {
+                      KeyKind::Button(_) => DevType::Mouse,
+                      _                  => DevType::Keyboard,
+                },
+            };
+
+            matches!(dev_type, DevType::Mouse)
+        });
+
+        if !mouse.is_empty() {
+            log::debug!("mouse <= {:?}", mouse);
+            self.mouse.write(&mouse).await?;
+        }
+
+        if !keyboard.is_empty() {
+            log::debug!("keyboard <= {:?}", keyboard);
+            self.keyboard.write(&keyboard).await?;
         }
+
+        Ok(())
     }
 }
@@ -8,11 +8,15 @@ impl EventManager {
         Err(Error::new(ErrorKind::Other, "Not implemented"))
     }
 
-    pub async fn read(&mut self) -> Result<Event, Error> {
+    pub async fn read(&mut self) -> Result<Vec<Event>, Error> {
         todo!()
     }
 
     pub async fn write(&mut self, _event: Event) -> Result<(), Error> {
         todo!()
     }
+
+    pub async fn write_frame(&mut self, _frame: &[Event]) -> Result<(), Error> {
+        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    }
 }
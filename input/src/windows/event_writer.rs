@@ -7,7 +7,7 @@ use tokio::time;
 use winapi::um::winuser::{self, INPUT};
 
 pub struct EventWriter {
-    event_sender: UnboundedSender<Event>,
+    event_sender: UnboundedSender<Vec<Event>>,
     error_receiver: Receiver<Error>,
 }
 
@@ -29,11 +29,15 @@ impl EventWriter {
     }
 
     pub async fn write(&mut self, event: Event) -> Result<(), Error> {
+        self.write_frame(&[event]).await
+    }
+
+    pub async fn write_frame(&mut self, frame: &[Event]) -> Result<(), Error> {
         if let Ok(err) = self.error_receiver.try_recv() {
             return Err(err);
         }
 
-        self.event_sender.send(event).unwrap();
+        self.event_sender.send(frame.to_vec()).unwrap();
         Ok(())
     }
 }
@@ -41,7 +45,7 @@ impl EventWriter {
 const REPEAT_INTERVAL: Duration = Duration::from_millis(20);
 const REPEAT_AFTER: Duration = Duration::from_millis(500);
 
-async fn handle_events(mut receiver: UnboundedReceiver<Event>) -> Result<(), Error> {
+async fn handle_events(mut receiver: UnboundedReceiver<Vec<Event>>) -> Result<(), Error> {
     let mut pressed: Option<(Key, Instant)> = None;
     let mut interval = time::interval(REPEAT_INTERVAL);
 
@@ -53,40 +57,50 @@ async fn handle_events(mut receiver: UnboundedReceiver<Event>) -> Result<(), Err
                         continue;
                     }
 
-                    write_event(Event::Key { kind: KeyKind::Key(key), direction: Direction::Down })?;
+                    write_frame(&[Event::Key { kind: KeyKind::Key(key), direction: Direction::Down }])?;
                 }
             }
-            event = receiver.recv() => {
-                let event = match event {
-                    Some(event) => event,
+            frame = receiver.recv() => {
+                let frame = match frame {
+                    Some(frame) => frame,
                     None => return Ok(()),
                 };
 
-                if let Event::Key { kind: KeyKind::Key(key), direction } = event {
-                    match direction {
-                        Direction::Up => {
-                            if pressed.map(|(k, _)| k == key).unwrap_or(false) {
-                                pressed = None;
+                for event in &frame {
+                    if let Event::Key { kind: KeyKind::Key(key), direction } = *event {
+                        match direction {
+                            Direction::Up => {
+                                if pressed.map(|(k, _)| k == key).unwrap_or(false) {
+                                    pressed = None;
+                                }
+                            }
+                            Direction::Down => {
+                                pressed = Some((key, Instant::now()));
                             }
-                        }
-                        Direction::Down => {
-                            pressed = Some((key, Instant::now()));
                         }
                     }
                 }
 
-                write_event(event)?;
+                write_frame(&frame)?;
             }
         }
     }
 }
 
-fn write_event(event: Event) -> Result<(), Error> {
-    if let Some(mut events) = event.to_raw() {
-        return write_raw(events.as_mut_slice());
+// Sends all events of a frame in a single SendInput call so that they are not interleaved with other input.
+fn write_frame(frame: &[Event]) -> Result<(), Error> {
+    let mut inputs = Vec::new();
+    for event in frame {
+        if let Some(mut events) = event.to_raw() {
+            inputs.extend_from_slice(events.as_mut_slice());
+        }
     }
 
-    Ok(())
+    if inputs.is_empty() {
+        return Ok(());
+    }
+
+    write_raw(&mut inputs)
 }
 
 fn write_raw(events: &mut [INPUT]) -> Result<(), Error> {
@@ -98,7 +112,7 @@ fn write_raw(events: &mut [INPUT]) -> Result<(), Error> {
         )
     };
 
-    if written != 1 {
+    if written as usize != events.len() {
         return Err(Error::last_os_error());
     }
 
@@ -0,0 +1,140 @@
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures_util::FutureExt;
+use input::{Direction, Event, Key, KeyKind};
+use net::{Framing, Message, MessageCodec, PROTOCOL_VERSION};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io::{self, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+// Compares the per message overhead of read_message and write_message, which allocate for
+// every message, with MessageCodec reusing its buffers. The I/O itself never blocks.
+// The "before" cases are the implementation both used to have, kept here as the baseline.
+
+fn events() -> Vec<Event> {
+    vec![Event::Key {
+        direction: Direction::Down,
+        kind: KeyKind::Key(Key::A),
+    }]
+}
+
+fn message() -> Message {
+    Message::Frame(events())
+}
+
+#[derive(Serialize, Deserialize)]
+enum OldMessage {
+    Frame(Vec<Event>),
+}
+
+async fn old_write_message<W>(mut writer: W, message: &OldMessage) -> Result<(), io::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let data =
+        bincode::serialize(message).map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    let length: u32 = data
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Serialized data is too large"))?;
+    writer.write_all(&length.to_le_bytes()).await?;
+    writer.write_all(&data).await?;
+
+    Ok(())
+}
+
+async fn old_read_message<R>(mut reader: R) -> Result<OldMessage, io::Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).await?;
+
+    let mut data = vec![0; u32::from_le_bytes(bytes) as usize];
+    reader.read_exact(&mut data).await?;
+
+    bincode::deserialize(&data).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+fn encode(c: &mut Criterion) {
+    let framing = Framing::for_version(PROTOCOL_VERSION).unwrap();
+    let message = message();
+
+    let mut group = c.benchmark_group("encode");
+    let old = OldMessage::Frame(events());
+    group.bench_function("before", |b| {
+        b.iter(|| {
+            old_write_message(tokio::io::sink(), black_box(&old))
+                .now_or_never()
+                .unwrap()
+                .unwrap()
+        })
+    });
+
+    group.bench_function("write_message", |b| {
+        b.iter(|| {
+            net::write_message(tokio::io::sink(), black_box(&message), &framing)
+                .now_or_never()
+                .unwrap()
+                .unwrap()
+        })
+    });
+
+    let mut codec = MessageCodec::new(framing);
+    let mut buffer = BytesMut::new();
+    group.bench_function("codec", |b| {
+        b.iter(|| {
+            buffer.clear();
+            codec.encode(black_box(&message), &mut buffer).unwrap();
+        })
+    });
+
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let framing = Framing::for_version(PROTOCOL_VERSION).unwrap();
+    let mut codec = MessageCodec::new(framing);
+
+    let mut frame = BytesMut::new();
+    codec.encode(&message(), &mut frame).unwrap();
+
+    let mut group = c.benchmark_group("decode");
+    let mut old = Vec::new();
+    old_write_message(&mut old, &OldMessage::Frame(events()))
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    group.bench_function("before", |b| {
+        b.iter(|| {
+            old_read_message(black_box(&old[..]))
+                .now_or_never()
+                .unwrap()
+                .unwrap()
+        })
+    });
+
+    group.bench_function("read_message", |b| {
+        b.iter(|| {
+            net::read_message(black_box(&frame[..]), &framing)
+                .now_or_never()
+                .unwrap()
+                .unwrap()
+        })
+    });
+
+    let mut buffer = BytesMut::new();
+    group.bench_function("codec", |b| {
+        b.iter(|| {
+            buffer.extend_from_slice(black_box(&frame));
+            codec.decode(&mut buffer).unwrap().unwrap().unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
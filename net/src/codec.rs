@@ -0,0 +1,214 @@
+use crate::wire::{LegacyMessage, RawMessage};
+use crate::{Error, Framing, Message, UnknownCode};
+use bytes::{BufMut, BytesMut};
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, ErrorKind};
+use tokio_util::codec::{Decoder, Encoder};
+
+// Encodes and decodes messages for use with tokio_util::codec::Framed and friends,
+// which reuse their buffers rather than allocating for every message.
+//
+// Unlike read_message, messages with keys or buttons we don't know about are yielded as
+// Ok(Err(UnknownCode)), since Framed stops reading after the first error.
+//
+// Only offered by the library for now, the client and server still use read_message and
+// write_message.
+#[derive(Clone, Copy, Debug)]
+pub struct MessageCodec {
+    framing: Framing,
+}
+
+impl MessageCodec {
+    pub fn new(framing: Framing) -> Self {
+        Self { framing }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Result<Message, UnknownCode>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let header_size = self.framing.header_size();
+        if src.len() < header_size {
+            return Ok(None);
+        }
+
+        let length = decode_header(&src[..header_size], &self.framing)?;
+        let frame_size = header_size + length;
+        if src.len() < frame_size {
+            src.reserve(frame_size - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_size);
+        decode_payload(&frame[header_size..], &self.framing).map(Some)
+    }
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = Error;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_frame(message, &self.framing, dst)
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_frame(&message, &self.framing, dst)
+    }
+}
+
+// Returns the length of the payload following the header, which must be complete.
+pub(crate) fn decode_header(header: &[u8], framing: &Framing) -> Result<usize, Error> {
+    let length = if framing.legacy {
+        header[0] as u32
+    } else {
+        u32::from_le_bytes(header.try_into().unwrap())
+    };
+
+    if length > framing.max_frame_size {
+        return Err(Error::FrameTooLarge {
+            size: length as usize,
+            max: framing.max_frame_size,
+        });
+    }
+
+    Ok(length as usize)
+}
+
+pub(crate) fn decode_payload(
+    data: &[u8],
+    framing: &Framing,
+) -> Result<Result<Message, UnknownCode>, Error> {
+    if framing.legacy {
+        let message: LegacyMessage =
+            bincode::deserialize(data).map_err(|err| Error::MalformedFrame(err))?;
+
+        return Ok(Ok(message.into()));
+    }
+
+    let message: RawMessage =
+        bincode::deserialize(data).map_err(|err| Error::MalformedFrame(err))?;
+
+    Ok(Message::try_from(message))
+}
+
+// Appends the header and the payload to the buffer, leaving it as it was on failure.
+// The payload is serialized in place, the header is filled in once its length is known.
+pub(crate) fn encode_frame(
+    message: &Message,
+    framing: &Framing,
+    dst: &mut BytesMut,
+) -> Result<(), Error> {
+    let start = dst.len();
+    let header_size = framing.header_size();
+    dst.put_bytes(0, header_size);
+
+    let result = if framing.legacy {
+        match LegacyMessage::new(message) {
+            Some(message) => bincode::serialize_into(dst.writer(), &message),
+            None => {
+                dst.truncate(start);
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "Message not supported by protocol version 1",
+                )
+                .into());
+            }
+        }
+    } else {
+        bincode::serialize_into(dst.writer(), &RawMessage::from(message))
+    };
+
+    if let Err(err) = result {
+        dst.truncate(start);
+        return Err(io::Error::new(ErrorKind::InvalidInput, err).into());
+    }
+
+    let length = dst.len() - start - header_size;
+    let length = match u32::try_from(length) {
+        Ok(length) if length <= framing.max_frame_size => length,
+        _ => {
+            dst.truncate(start);
+            return Err(Error::FrameTooLarge {
+                size: length,
+                max: framing.max_frame_size,
+            });
+        }
+    };
+
+    if framing.legacy {
+        dst[start] = length as u8;
+    } else {
+        dst[start..start + header_size].copy_from_slice(&length.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use input::{Direction, Event, Key, KeyKind};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn framed() {
+        let framing = Framing::for_version(crate::PROTOCOL_VERSION).unwrap();
+        let (reader, writer) = tokio::io::duplex(64);
+        let mut reader = FramedRead::new(reader, MessageCodec::new(framing));
+        let mut writer = FramedWrite::new(writer, MessageCodec::new(framing));
+
+        let frame = vec![Event::Key {
+            direction: Direction::Down,
+            kind: KeyKind::Key(Key::A),
+        }];
+
+        // More than fits into the pipe at once, so that frames are split across reads.
+        tokio::spawn(async move {
+            for sequence in 0..100 {
+                let message = Message::SequencedFrame(sequence, frame.clone());
+                writer.send(&message).await.unwrap();
+            }
+        });
+
+        for expected in 0..100 {
+            match reader.next().await.unwrap().unwrap().unwrap() {
+                Message::SequencedFrame(sequence, frame) => {
+                    assert_eq!(sequence, expected);
+                    assert_eq!(frame.len(), 1);
+                }
+                message => panic!("Unexpected message {:?}", message),
+            }
+        }
+
+        assert!(reader.next().await.is_none());
+    }
+
+    #[test]
+    fn too_large() {
+        let framing = Framing::for_version(crate::PROTOCOL_VERSION)
+            .unwrap()
+            .max_frame_size(8);
+        let mut codec = MessageCodec::new(framing);
+
+        let mut buffer = BytesMut::new();
+        let message = Message::Rejected("Too long to fit".to_owned());
+        assert!(matches!(
+            codec.encode(&message, &mut buffer),
+            Err(Error::FrameTooLarge { max: 8, .. })
+        ));
+        assert!(buffer.is_empty());
+
+        buffer.extend_from_slice(&9u32.to_le_bytes());
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(Error::FrameTooLarge { size: 9, max: 8 })
+        ));
+    }
+}
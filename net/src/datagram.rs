@@ -0,0 +1,224 @@
+use crate::wire::RawEvent;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use input::Event;
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Debug, Formatter};
+use std::io::{Error, ErrorKind};
+
+// Datagrams carry events which can be lost without much harm (mouse movement and scrolling),
+// so that a single lost packet doesn't stall everything behind it as it would on the TCP stream.
+//
+// Each datagram is laid out as follows:
+//
+// session (u64) | sequence (u64) | encrypted events + tag
+//
+// Integers are little endian, events are a bincode encoded Vec<RawEvent> sealed with ChaCha20-Poly1305,
+// using the session and sequence as additional data. The key is sent over the TLS stream and never reused,
+// the nonce consists of the direction and the sequence number, which is never repeated within a direction.
+// Datagrams received out of order are dropped.
+
+const HEADER_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct DatagramKey([u8; 32]);
+
+impl DatagramKey {
+    pub fn generate() -> Result<Self, Error> {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).map_err(Error::from)?;
+
+        Ok(Self(key))
+    }
+}
+
+// Don't leak the key into logs.
+impl Debug for DatagramKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "DatagramKey(..)")
+    }
+}
+
+// Sent by the server over the TLS stream to set up the datagram channel, requires Capabilities::DATAGRAM.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct DatagramSetup {
+    // UDP port of the server, the host is the same as the one of the TLS stream.
+    pub port: u16,
+    pub session: u64,
+    pub key: DatagramKey,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatagramDirection {
+    ServerToClient,
+    // The client only ever sends empty frames, which let the server learn its address and keep NAT mappings alive.
+    ClientToServer,
+}
+
+impl DatagramDirection {
+    fn nonce(self, sequence: u64) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&(self as u32).to_le_bytes());
+        nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+
+        nonce
+    }
+}
+
+pub struct DatagramSealer {
+    cipher: ChaCha20Poly1305,
+    session: u64,
+    direction: DatagramDirection,
+    sequence: u64,
+}
+
+impl DatagramSealer {
+    pub fn new(setup: &DatagramSetup, direction: DatagramDirection) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&setup.key.0)),
+            session: setup.session,
+            direction,
+            sequence: 0,
+        }
+    }
+
+    pub fn seal(&mut self, events: &[Event]) -> Result<Vec<u8>, Error> {
+        let events = events
+            .iter()
+            .copied()
+            .map(RawEvent::from)
+            .collect::<Vec<_>>();
+        let data =
+            bincode::serialize(&events).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+
+        let mut datagram = Vec::with_capacity(HEADER_SIZE + data.len() + 16);
+        datagram.extend_from_slice(&self.session.to_le_bytes());
+        datagram.extend_from_slice(&self.sequence.to_le_bytes());
+
+        let nonce = self.direction.nonce(self.sequence);
+        let sealed = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &data,
+                    aad: &datagram,
+                },
+            )
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Failed to seal datagram"))?;
+        datagram.extend_from_slice(&sealed);
+
+        self.sequence += 1;
+        Ok(datagram)
+    }
+}
+
+pub struct DatagramOpener {
+    cipher: ChaCha20Poly1305,
+    session: u64,
+    direction: DatagramDirection,
+    next: u64,
+}
+
+impl DatagramOpener {
+    pub fn new(setup: &DatagramSetup, direction: DatagramDirection) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&setup.key.0)),
+            session: setup.session,
+            direction,
+            next: 0,
+        }
+    }
+
+    // Returns None for datagrams which are stale or duplicated.
+    pub fn open(&mut self, datagram: &[u8]) -> Result<Option<Vec<Event>>, Error> {
+        let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+
+        if session_of(datagram) != Some(self.session) {
+            return Err(invalid("Datagram belongs to another session"));
+        }
+
+        let sequence = u64::from_le_bytes(datagram[8..HEADER_SIZE].try_into().unwrap());
+        if sequence < self.next {
+            return Ok(None);
+        }
+
+        let nonce = self.direction.nonce(sequence);
+        let data = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &datagram[HEADER_SIZE..],
+                    aad: &datagram[..HEADER_SIZE],
+                },
+            )
+            .map_err(|_| invalid("Datagram failed authentication"))?;
+
+        let events: Vec<RawEvent> =
+            bincode::deserialize(&data).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        let events = events
+            .into_iter()
+            .map(Event::try_from)
+            .collect::<Result<_, _>>()
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+        self.next = sequence + 1;
+        Ok(Some(events))
+    }
+}
+
+// Used to find out which session a datagram belongs to before opening it.
+pub fn session_of(datagram: &[u8]) -> Option<u64> {
+    if datagram.len() < HEADER_SIZE {
+        return None;
+    }
+
+    Some(u64::from_le_bytes(datagram[..8].try_into().unwrap()))
+}
+
+// Whether the frame only consists of events it's fine to lose.
+pub fn is_lossy(frame: &[Event]) -> bool {
+    frame
+        .iter()
+        .all(|event| matches!(event, Event::MouseMove { .. } | Event::MouseScroll { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Axis;
+
+    #[test]
+    fn seal_open() {
+        let setup = DatagramSetup {
+            port: 5258,
+            session: 7,
+            key: DatagramKey::generate().unwrap(),
+        };
+
+        let mut sealer = DatagramSealer::new(&setup, DatagramDirection::ServerToClient);
+        let mut opener = DatagramOpener::new(&setup, DatagramDirection::ServerToClient);
+        let frame = [Event::MouseMove {
+            axis: Axis::X,
+            delta: -3,
+        }];
+
+        let first = sealer.seal(&frame).unwrap();
+        let second = sealer.seal(&frame).unwrap();
+        assert_eq!(session_of(&first), Some(7));
+
+        // Newer datagrams win, older and replayed ones are dropped.
+        assert_eq!(
+            opener.open(&second).unwrap().map(|frame| frame.len()),
+            Some(1)
+        );
+        assert!(opener.open(&first).unwrap().is_none());
+        assert!(opener.open(&second).unwrap().is_none());
+
+        // A datagram sealed for the other direction must not open.
+        let mut opener = DatagramOpener::new(&setup, DatagramDirection::ClientToServer);
+        assert!(opener.open(&first).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::ops::RangeInclusive;
+
+// Errors returned by the handshake and by reading and writing messages, so that callers
+// can tell whether trying again has any chance of success.
+#[derive(Debug)]
+pub enum Error {
+    // The peer closed the connection in between messages.
+    PeerClosed,
+    Timeout,
+    // Protocol versions supported by both sides, neither range overlaps the other.
+    VersionMismatch {
+        ours: RangeInclusive<u16>,
+        theirs: RangeInclusive<u16>,
+    },
+    // The frame arrived in full, but its payload isn't a valid message.
+    MalformedFrame(Box<dyn error::Error + Send + Sync>),
+    FrameTooLarge {
+        size: usize,
+        max: u32,
+    },
+    // Not produced by this crate, but by callers setting up TLS before the handshake,
+    // see Error::tls.
+    Tls(Box<dyn error::Error + Send + Sync>),
+    // A failed Noise handshake or an unexpected key, instead of TLS.
+    Noise(Box<dyn error::Error + Send + Sync>),
+    Io(io::Error),
+}
+
+impl Error {
+    // Whether the same attempt may succeed later on, e.g. after reconnecting.
+    // Mismatched versions, malformed frames, TLS and Noise errors are down to the peer or its configuration.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::PeerClosed | Self::Timeout | Self::Io(_))
+    }
+
+    // Sorts out errors of a TLS handshake. Failing to read or write the connection underneath
+    // (e.g. because it dropped) is an I/O error, anything else a TLS error.
+    // Both TLS implementations report protocol and certificate errors as InvalidData.
+    pub fn tls<E>(err: E) -> Self
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        let err = match err.into().downcast::<io::Error>() {
+            Ok(err) if err.kind() == io::ErrorKind::InvalidData => return Self::Tls(err),
+            Ok(err) => return Self::Io(*err),
+            Err(err) => err,
+        };
+
+        let mut source = err.source();
+        while let Some(current) = source {
+            if let Some(io) = current.downcast_ref::<io::Error>() {
+                if io.kind() != io::ErrorKind::InvalidData {
+                    return Self::Io(io::Error::new(io.kind(), err));
+                }
+            }
+
+            source = current.source();
+        }
+
+        Self::Tls(err)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::PeerClosed => write!(f, "Connection closed"),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::VersionMismatch { ours, theirs } => write!(
+                f,
+                "Incompatible protocol version (got {} to {}, expecting {} to {})",
+                theirs.start(),
+                theirs.end(),
+                ours.start(),
+                ours.end()
+            ),
+            Self::MalformedFrame(err) => write!(f, "Malformed frame: {}", err),
+            Self::FrameTooLarge { size, max } => {
+                write!(f, "Frame too large ({} bytes, maximum is {})", size, max)
+            }
+            Self::Tls(err) => write!(f, "TLS error: {}", err),
+            Self::Noise(err) => write!(f, "Noise error: {}", err),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::MalformedFrame(err) | Self::Tls(err) | Self::Noise(err) => Some(err.as_ref()),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::PeerClosed => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            Error::Timeout => io::Error::new(io::ErrorKind::TimedOut, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tls() {
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(Error::tls(reset).is_transient());
+
+        let protocol = io::Error::new(io::ErrorKind::InvalidData, "bad certificate");
+        assert!(matches!(Error::tls(protocol), Error::Tls(_)));
+
+        // Wrapped by the TLS implementation, as native-tls does.
+        #[derive(Debug)]
+        struct Wrapped(io::Error);
+
+        impl Display for Wrapped {
+            fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "handshake failed")
+            }
+        }
+
+        impl error::Error for Wrapped {
+            fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let wrapped = Wrapped(io::ErrorKind::UnexpectedEof.into());
+        match Error::tls(wrapped) {
+            Error::Io(err) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            err => panic!("{:?}", err),
+        }
+
+        assert!(matches!(Error::tls("no certificate"), Error::Tls(_)));
+    }
+}
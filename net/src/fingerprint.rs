@@ -0,0 +1,117 @@
+use serde::de::{self, Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
+// SHA-256 digest of the public key (the DER encoded SubjectPublicKeyInfo) of a certificate, written as sha256:HEX.
+// Unlike a digest of the whole certificate, it stays the same when the certificate is renewed with the same key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    // None if the DER encoded certificate can't be parsed.
+    pub fn of(certificate: &[u8]) -> Option<Self> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+        Some(Self(Sha256::digest(certificate.public_key().raw).into()))
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "sha256:")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for Fingerprint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+// Also accepts upper case and colon separated digits.
+impl FromStr for Fingerprint {
+    type Err = &'static str;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let data = data
+            .strip_prefix("sha256:")
+            .ok_or("Fingerprint has to start with sha256:")?;
+
+        let mut digits = data.chars().filter(|c| *c != ':');
+        let mut digest = [0; 32];
+        for byte in &mut digest {
+            let mut digit = || {
+                digits
+                    .next()
+                    .and_then(|c| c.to_digit(16))
+                    .ok_or("Fingerprint has to consist of 64 hexadecimal digits")
+            };
+
+            *byte = (digit()? << 4 | digit()?) as u8;
+        }
+
+        if digits.next().is_some() {
+            return Err("Fingerprint has to consist of 64 hexadecimal digits");
+        }
+
+        Ok(Self(digest))
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = String::deserialize(deserializer)?;
+        data.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn of() {
+        let certificate = base64::decode(concat!(
+            "MIIBcjCCARmgAwIBAgIUT8A4Wo96bGHpBCnNguprTU1JCAowCgYIKoZIzj0EAwIwDzENMAsGA1UEAwwEcmt2bTAe",
+            "Fw0yNjEwMTcwMjUwNTdaFw0zNjEwMTQwMjUwNTdaMA8xDTALBgNVBAMMBHJrdm0wWTATBgcqhkjOPQIBBggqhkjO",
+            "PQMBBwNCAARE5BmviFTafdP5OVq/C7ZJUV7qJydW8Cj+WVWvA/PU43r9Rf9oWOjtCHMypE+3PHxy49ztMi2WI/v6",
+            "FJ58rUBno1MwUTAdBgNVHQ4EFgQUMAmQ8nyRFPdYb4FTnpTjJ6oZv8EwHwYDVR0jBBgwFoAUMAmQ8nyRFPdYb4FT",
+            "npTjJ6oZv8EwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiB6KjxarGweFvU8UYcxU7EeifV+WQUw",
+            "7v9gTo59/4SlRwIgBPIPJS8PQweh6X3PE1Y9mQP/Exg6sVC/WCuqXH9bWsc=",
+        ))
+        .unwrap();
+
+        // As printed by openssl x509 -noout -pubkey | openssl pkey -pubin -outform DER | openssl dgst -sha256.
+        let expected = "sha256:7073fb9fcb0e6afb3b9a2504376bbecc4e400261d97693feea03441c5aee8e85";
+        assert_eq!(Fingerprint::of(&certificate), expected.parse().ok());
+        assert_eq!(Fingerprint::of(b"certificate"), None);
+    }
+
+    #[test]
+    fn parse() {
+        let fingerprint = Fingerprint([0xab; 32]);
+        assert_eq!(fingerprint.to_string().parse(), Ok(fingerprint));
+
+        let colons = fingerprint
+            .to_string()
+            .trim_start_matches("sha256:")
+            .to_uppercase()
+            .as_bytes()
+            .chunks(2)
+            .map(|digits| std::str::from_utf8(digits).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(format!("sha256:{}", colons).parse(), Ok(fingerprint));
+
+        assert!("sha256:abcd".parse::<Fingerprint>().is_err());
+        assert!("sha1:abcd".parse::<Fingerprint>().is_err());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::{Error, Framing, KeepAlive, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, ErrorKind};
+use std::ops::{BitAnd, BitOr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+
+// The first protocol version which exchanges version ranges and capabilities.
+const RANGE_VERSION: u16 = 3;
+// The first protocol version in which clients introduce themselves with a hello message.
+const HELLO_VERSION: u16 = 4;
+
+// Optional protocol features. A feature may only be used if both peers advertise it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const BATCHED_FRAMES: Self = Self(1 << 0);
+    pub const ABSOLUTE_POINTER: Self = Self(1 << 1);
+    pub const LED_SYNC: Self = Self(1 << 2);
+    pub const PING: Self = Self(1 << 3);
+    pub const REPORTS: Self = Self(1 << 4);
+    pub const DATAGRAM: Self = Self(1 << 5);
+    pub const RESUME: Self = Self(1 << 6);
+    pub const KEEP_ALIVE: Self = Self(1 << 7);
+
+    // Everything this build of rkvm knows how to handle.
+    pub const SUPPORTED: Self = Self(
+        Self::BATCHED_FRAMES.0
+            | Self::PING.0
+            | Self::REPORTS.0
+            | Self::DATAGRAM.0
+            | Self::RESUME.0
+            | Self::KEEP_ALIVE.0,
+    );
+
+    const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::BATCHED_FRAMES, "BATCHED_FRAMES"),
+        (Self::ABSOLUTE_POINTER, "ABSOLUTE_POINTER"),
+        (Self::LED_SYNC, "LED_SYNC"),
+        (Self::PING, "PING"),
+        (Self::REPORTS, "REPORTS"),
+        (Self::DATAGRAM, "DATAGRAM"),
+        (Self::RESUME, "RESUME"),
+        (Self::KEEP_ALIVE, "KEEP_ALIVE"),
+    ];
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    // Unknown bits are kept, they are simply never going to be in our own set.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl Debug for Capabilities {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut set = f.debug_set();
+        let mut known = 0;
+        for (capability, name) in Self::NAMES.iter().copied() {
+            if self.contains(capability) {
+                set.entry(&format_args!("{}", name));
+            }
+
+            known |= capability.0;
+        }
+
+        if self.0 & !known != 0 {
+            set.entry(&format_args!("{:#x}", self.0 & !known));
+        }
+
+        set.finish()
+    }
+}
+
+// Which end of the connection we are, regardless of which one connected to the other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+// The outcome of a successful handshake.
+#[derive(Clone, Copy, Debug)]
+pub struct Handshake {
+    pub version: u16,
+    // Capabilities supported by both sides.
+    pub capabilities: Capabilities,
+    pub framing: Framing,
+    // Agreed upon by both sides, the default unless both support KEEP_ALIVE.
+    pub keep_alive: KeepAlive,
+}
+
+impl Handshake {
+    // Whether the client is going to send Message::Hello before anything else.
+    pub fn has_hello(&self) -> bool {
+        self.version >= HELLO_VERSION
+    }
+
+    // Whether the client is going to send Message::Resume right after its hello.
+    pub fn has_resume(&self) -> bool {
+        self.has_hello() && self.capabilities.contains(Capabilities::RESUME)
+    }
+}
+
+// Both sides run the same procedure:
+//
+// 1. The client writes its highest supported version as a little endian u16, the server reads it
+//    and answers with the lower one of its own and the client's. This is all protocol versions
+//    1 and 2 did, with both sides insisting on their own version, so older clients can still
+//    connect. Older servers reject newer clients though.
+// 2. If the peer's version is at least 3, write our lowest supported version (u16)
+//    and our capabilities (u32) and read the peer's.
+//
+// The highest version within both ranges is then chosen.
+//
+// 3. If both sides support KEEP_ALIVE, write our keep alive interval and timeout
+//    in milliseconds (u32 each) and read the peer's.
+//
+// The whole exchange has to complete within the timeout of our own keep alive settings,
+// the negotiated ones are used from then on.
+pub async fn handshake<S>(
+    stream: S,
+    role: Role,
+    capabilities: Capabilities,
+    keep_alive: KeepAlive,
+) -> Result<Handshake, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    time::timeout(
+        keep_alive.timeout(),
+        exchange(stream, role, capabilities, keep_alive),
+    )
+    .await
+    .unwrap_or(Err(Error::Timeout))
+}
+
+async fn exchange<S>(
+    mut stream: S,
+    role: Role,
+    capabilities: Capabilities,
+    keep_alive: KeepAlive,
+) -> Result<Handshake, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let their_max = match role {
+        Role::Client => {
+            write_version(&mut stream, PROTOCOL_VERSION).await?;
+            stream.flush().await?;
+            read_version(&mut stream).await.map_err(closed)?
+        }
+        Role::Server => {
+            let their_max = read_version(&mut stream).await.map_err(closed)?;
+            write_version(&mut stream, PROTOCOL_VERSION.min(their_max)).await?;
+            stream.flush().await?;
+            their_max
+        }
+    };
+
+    let (their_min, their_capabilities) = if their_max >= RANGE_VERSION {
+        stream
+            .write_all(&MIN_PROTOCOL_VERSION.to_le_bytes())
+            .await?;
+        stream.write_all(&capabilities.bits().to_le_bytes()).await?;
+        stream.flush().await?;
+
+        let their_min = read_version(&mut stream).await.map_err(closed)?;
+
+        let mut bytes = [0; 4];
+        stream.read_exact(&mut bytes).await.map_err(closed)?;
+
+        (
+            their_min,
+            Capabilities::from_bits(u32::from_le_bytes(bytes)),
+        )
+    } else {
+        (their_max, Capabilities::empty())
+    };
+
+    let version = PROTOCOL_VERSION.min(their_max);
+    let framing = Some(version)
+        .filter(|version| *version >= MIN_PROTOCOL_VERSION.max(their_min))
+        .and_then(Framing::for_version)
+        .ok_or(Error::VersionMismatch {
+            ours: MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION,
+            theirs: their_min..=their_max,
+        })?;
+
+    let capabilities = capabilities & their_capabilities;
+    let their_keep_alive = if capabilities.contains(Capabilities::KEEP_ALIVE) {
+        stream.write_all(&keep_alive.to_bytes()).await?;
+        stream.flush().await?;
+
+        let mut bytes = [0; 8];
+        stream.read_exact(&mut bytes).await.map_err(closed)?;
+
+        KeepAlive::from_bytes(bytes)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid keep alive settings"))?
+    } else {
+        KeepAlive::default()
+    };
+
+    Ok(Handshake {
+        version,
+        capabilities,
+        framing,
+        keep_alive: keep_alive.agree(their_keep_alive),
+    })
+}
+
+// Hanging up mid-handshake is reported the same way as hanging up between messages.
+pub(crate) fn closed(err: io::Error) -> Error {
+    match err.kind() {
+        ErrorKind::UnexpectedEof => Error::PeerClosed,
+        _ => err.into(),
+    }
+}
+
+pub async fn read_version<R>(mut reader: R) -> Result<u16, io::Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes).await?;
+
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub async fn write_version<W>(mut writer: W, version: u16) -> Result<(), io::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&version.to_le_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MESSAGE_TIMEOUT;
+
+    #[tokio::test]
+    async fn version_mismatch() {
+        let (mut ours, mut theirs) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            write_version(&mut theirs, PROTOCOL_VERSION + 2)
+                .await
+                .unwrap();
+            theirs
+                .write_all(&(PROTOCOL_VERSION + 1).to_le_bytes())
+                .await
+                .unwrap();
+            theirs.write_all(&0u32.to_le_bytes()).await.unwrap();
+
+            // Keep the stream open until the other side is done.
+            let _ = theirs.read(&mut [0; 64]).await;
+            let _ = theirs.read(&mut [0; 64]).await;
+        });
+
+        match handshake(
+            &mut ours,
+            Role::Client,
+            Capabilities::SUPPORTED,
+            KeepAlive::default(),
+        )
+        .await
+        {
+            Err(Error::VersionMismatch { ours, theirs }) => {
+                assert_eq!(ours, MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION);
+                assert_eq!(theirs, PROTOCOL_VERSION + 1..=PROTOCOL_VERSION + 2);
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_client() {
+        let (mut ours, mut theirs) = tokio::io::duplex(64);
+        // What clients speaking protocol version 1 do.
+        let client = tokio::spawn(async move {
+            write_version(&mut theirs, 1).await.unwrap();
+            read_version(&mut theirs).await.unwrap()
+        });
+
+        let result = handshake(
+            &mut ours,
+            Role::Server,
+            Capabilities::SUPPORTED,
+            KeepAlive::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.version, 1);
+        assert!(!result.has_hello());
+        assert_eq!(client.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn peer_closed() {
+        let (mut ours, mut theirs) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            read_version(&mut theirs).await.unwrap();
+        });
+
+        let result = handshake(
+            &mut ours,
+            Role::Client,
+            Capabilities::SUPPORTED,
+            KeepAlive::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::PeerClosed)), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn keep_alive() {
+        use std::time::Duration;
+
+        let (mut ours, mut theirs) = tokio::io::duplex(64);
+        let slow = KeepAlive::new(Duration::from_secs(10), Duration::from_secs(60)).unwrap();
+        let (ours, theirs) = tokio::join!(
+            handshake(
+                &mut ours,
+                Role::Client,
+                Capabilities::SUPPORTED,
+                KeepAlive::default()
+            ),
+            handshake(&mut theirs, Role::Server, Capabilities::SUPPORTED, slow),
+        );
+
+        // Each side waits as long as it's configured to, but sends often enough for the other.
+        assert_eq!(ours.unwrap().keep_alive, KeepAlive::default());
+        let expected = KeepAlive::new(MESSAGE_TIMEOUT / 2, Duration::from_secs(60));
+        assert_eq!(Some(theirs.unwrap().keep_alive), expected);
+
+        // Without the capability, the peer is assumed to use the defaults.
+        let (mut ours, mut theirs) = tokio::io::duplex(64);
+        let (ours, _) = tokio::join!(
+            handshake(&mut ours, Role::Server, Capabilities::SUPPORTED, slow),
+            handshake(&mut theirs, Role::Client, Capabilities::empty(), slow),
+        );
+
+        let expected = KeepAlive::new(MESSAGE_TIMEOUT / 2, Duration::from_secs(60));
+        assert_eq!(Some(ours.unwrap().keep_alive), expected);
+    }
+}
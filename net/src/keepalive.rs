@@ -0,0 +1,157 @@
+use crate::MESSAGE_TIMEOUT;
+use serde::Deserialize;
+use socket2::{SockRef, TcpKeepalive};
+use std::convert::TryInto;
+use std::io::Error;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+// The shortest interval a peer can make us send at by announcing a short timeout.
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often each side sends something while otherwise idle, and how long it waits for the peer
+// to send anything before considering it dead.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeepAlive {
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl KeepAlive {
+    // The timeout has to be at least twice the interval, so that a single late message isn't fatal.
+    pub fn new(interval: Duration, timeout: Duration) -> Option<Self> {
+        if interval.is_zero() || timeout < interval * 2 {
+            return None;
+        }
+
+        Some(Self { interval, timeout })
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // Each side keeps waiting as long as it itself is configured to, but sends often enough for
+    // the peer's timeout. The peer can't make us send more often than MIN_INTERVAL.
+    pub(crate) fn agree(self, theirs: Self) -> Self {
+        let interval = self.interval.min(theirs.timeout.max(MIN_INTERVAL * 2) / 2);
+
+        Self {
+            interval,
+            timeout: self.timeout,
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 8] {
+        let millis =
+            |duration: Duration| -> u32 { duration.as_millis().try_into().unwrap_or(u32::MAX) };
+
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&millis(self.interval).to_le_bytes());
+        bytes[4..].copy_from_slice(&millis(self.timeout).to_le_bytes());
+
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 8]) -> Option<Self> {
+        let millis = |bytes: &[u8]| {
+            Duration::from_millis(u32::from_le_bytes(bytes.try_into().unwrap()).into())
+        };
+
+        Self::new(millis(&bytes[..4]), millis(&bytes[4..]))
+    }
+}
+
+// What peers without the KEEP_ALIVE capability use.
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            interval: MESSAGE_TIMEOUT / 2,
+            timeout: MESSAGE_TIMEOUT,
+        }
+    }
+}
+
+// The keep-alive section of the client and server configs.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct KeepAliveConfig {
+    // How often to send something while idle.
+    pub interval_ms: u64,
+    // How long to wait for the peer before considering it dead.
+    pub timeout_ms: u64,
+    // Also enable OS-level TCP keepalive (and TCP_USER_TIMEOUT on Linux) with the same settings.
+    pub tcp: bool,
+}
+
+impl KeepAliveConfig {
+    // None if the timeout is less than twice the interval.
+    pub fn to_net(self) -> Option<KeepAlive> {
+        KeepAlive::new(
+            Duration::from_millis(self.interval_ms),
+            Duration::from_millis(self.timeout_ms),
+        )
+    }
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        let keep_alive = KeepAlive::default();
+
+        Self {
+            interval_ms: keep_alive.interval.as_millis() as _,
+            timeout_ms: keep_alive.timeout.as_millis() as _,
+            tcp: false,
+        }
+    }
+}
+
+// Enables TCP keepalive on the socket, so that the OS notices a dead peer even if nothing is being written.
+// On Linux, TCP_USER_TIMEOUT additionally bounds how long sent data may go unacknowledged.
+pub fn set_tcp_keep_alive(stream: &TcpStream, keep_alive: &KeepAlive) -> Result<(), Error> {
+    let params = TcpKeepalive::new().with_time(keep_alive.interval);
+    #[cfg(any(target_os = "linux", windows))]
+    let params = params.with_interval(keep_alive.interval);
+    #[cfg(target_os = "linux")]
+    let params = params.with_retries(
+        (keep_alive.timeout.as_millis() / keep_alive.interval.as_millis())
+            .try_into()
+            .unwrap_or(u32::MAX),
+    );
+
+    let socket = SockRef::from(stream);
+    socket.set_tcp_keepalive(&params)?;
+    #[cfg(target_os = "linux")]
+    socket.set_tcp_user_timeout(Some(keep_alive.timeout))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agree() {
+        let lan = KeepAlive::new(Duration::from_millis(250), Duration::from_secs(1)).unwrap();
+        let satellite = KeepAlive::new(Duration::from_secs(5), Duration::from_secs(30)).unwrap();
+
+        // Both keep their own timeouts, the satellite link sends often enough for the LAN one.
+        assert_eq!(lan.agree(satellite), lan);
+        let agreed = satellite.agree(lan);
+        assert_eq!(agreed.interval(), Duration::from_millis(500));
+        assert_eq!(agreed.timeout(), Duration::from_secs(30));
+
+        // A peer announcing a tiny timeout doesn't get us to flood it.
+        let eager = KeepAlive::new(Duration::from_millis(1), Duration::from_millis(2)).unwrap();
+        assert_eq!(lan.agree(eager).interval(), MIN_INTERVAL);
+        assert_eq!(lan.agree(eager).timeout(), Duration::from_secs(1));
+
+        assert_eq!(KeepAlive::from_bytes(agreed.to_bytes()), Some(agreed));
+        assert_eq!(KeepAlive::from_bytes([0; 8]), None);
+    }
+}
@@ -1,67 +1,211 @@
+mod codec;
+mod datagram;
+mod duplex;
+mod error;
+mod fingerprint;
+mod handshake;
+mod keepalive;
+mod mux;
+mod noise;
+mod resume;
+mod vsock;
+mod websocket;
+mod wire;
+
+pub use codec::MessageCodec;
+pub use datagram::{
+    is_lossy, session_of, DatagramDirection, DatagramKey, DatagramOpener, DatagramSealer,
+    DatagramSetup,
+};
+pub use duplex::Duplex;
+pub use error::Error;
+pub use fingerprint::Fingerprint;
+pub use handshake::{handshake, read_version, write_version, Capabilities, Handshake, Role};
+pub use keepalive::{set_tcp_keep_alive, KeepAlive, KeepAliveConfig};
+pub use mux::{multiplex, Channel, Channels, MuxStream};
+pub use noise::{Noise, Passphrase, PrivateKey, PublicKey};
+pub use resume::{Resume, SessionInfo, SessionTicket};
+pub use vsock::VsockAddr;
+#[cfg(target_os = "linux")]
+pub use vsock::{VsockListener, VsockStream};
+pub use websocket::WebSocket;
+pub use wire::UnknownCode;
+
+use bytes::BytesMut;
 use input::Event;
 use serde::{Deserialize, Serialize};
-use std::convert::TryInto;
-use std::io::{Error, ErrorKind};
+use std::io::{self, ErrorKind};
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 // Is it bold to assume there won't be more than 65536 protocol versions?
-pub const PROTOCOL_VERSION: u16 = 1;
+pub const PROTOCOL_VERSION: u16 = 4;
+// The oldest protocol version we can still talk to.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
 pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long the server keeps a session around after its connection has been lost.
+pub const RESUME_TIMEOUT: Duration = Duration::from_secs(30);
+// Frames larger than this are rejected before any memory is allocated for them.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+// Describes how messages are delimited and encoded on the wire.
+#[derive(Clone, Copy, Debug)]
+pub struct Framing {
+    // Protocol version 1 used a single byte length prefix and encoded keys by their variant index.
+    legacy: bool,
+    max_frame_size: u32,
+}
+
+impl Framing {
+    pub fn for_version(version: u16) -> Option<Self> {
+        let framing = match version {
+            1 => Self {
+                legacy: true,
+                max_frame_size: u8::MAX as _,
+            },
+            2..=4 => Self {
+                legacy: false,
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            },
+            _ => return None,
+        };
+
+        Some(framing)
+    }
+
+    pub fn max_frame_size(mut self, max_frame_size: u32) -> Self {
+        self.max_frame_size = if self.legacy {
+            max_frame_size.min(u8::MAX as _)
+        } else {
+            max_frame_size
+        };
 
-pub async fn read_version<R>(mut reader: R) -> Result<u16, Error>
+        self
+    }
+
+    fn header_size(&self) -> usize {
+        if self.legacy {
+            1
+        } else {
+            4
+        }
+    }
+}
+
+pub async fn read_message<R>(mut reader: R, framing: &Framing) -> Result<Message, Error>
 where
     R: AsyncRead + Unpin,
 {
-    let mut bytes = [0; 2];
-    reader.read_exact(&mut bytes).await?;
+    let mut header = [0; 4];
+    let header = &mut header[..framing.header_size()];
+    read_frame_part(&mut reader, header, "header").await?;
+    let length = codec::decode_header(header, framing)?;
 
-    Ok(u16::from_le_bytes(bytes))
+    let mut data = vec![0; length];
+    read_frame_part(&mut reader, &mut data, "payload").await?;
+
+    codec::decode_payload(&data, framing)?.map_err(|err| Error::MalformedFrame(err.into()))
 }
 
-pub async fn write_version<W>(mut writer: W, version: u16) -> Result<(), Error>
+pub async fn write_message<W>(
+    mut writer: W,
+    message: &Message,
+    framing: &Framing,
+) -> Result<(), Error>
 where
     W: AsyncWrite + Unpin,
 {
-    writer.write_all(&version.to_le_bytes()).await
+    // The whole frame is written at once and flushed, so that message based transports
+    // such as WebSocket carry it in a single message without delay.
+    let mut frame = BytesMut::new();
+    codec::encode_frame(message, framing, &mut frame)?;
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+
+    Ok(())
 }
 
-pub async fn read_message<R>(mut reader: R) -> Result<Message, Error>
+// Like read_exact, but tells apart a connection closed between frames from one closed mid-frame.
+async fn read_frame_part<R>(reader: &mut R, data: &mut [u8], part: &str) -> Result<(), Error>
 where
     R: AsyncRead + Unpin,
 {
-    let length = {
-        let mut bytes = [0; 1];
-        reader.read_exact(&mut bytes).await?;
+    let mut read = 0;
+    while read < data.len() {
+        let count = reader.read(&mut data[read..]).await?;
+        if count == 0 {
+            if read == 0 && part == "header" {
+                return Err(Error::PeerClosed);
+            }
 
-        bytes[0]
-    };
+            let message = format!(
+                "Truncated frame {} (got {} of {} bytes)",
+                part,
+                read,
+                data.len()
+            );
 
-    let mut data = vec![0; length as usize];
-    reader.read_exact(&mut data).await?;
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, message).into());
+        }
 
-    bincode::deserialize(&data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
-}
-
-pub async fn write_message<W>(mut writer: W, message: &Message) -> Result<(), Error>
-where
-    W: AsyncWrite + Unpin,
-{
-    let data =
-        bincode::serialize(&message).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
-    let length: u8 = data
-        .len()
-        .try_into()
-        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Serialized data is too large"))?;
-    writer.write_all(&length.to_le_bytes()).await?;
-    writer.write_all(&data).await?;
+        read += count;
+    }
 
     Ok(())
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug)]
 pub enum Message {
     Event(Event),
     // Sent only to keep the connection alive.
     KeepAlive,
+    // Sent by the client right after the handshake.
+    Hello(Hello),
+    // Sent by the server before closing the connection if it refuses the client.
+    Rejected(String),
+    // Events to be applied at once, requires Capabilities::BATCHED_FRAMES.
+    Frame(Vec<Event>),
+    // Sent by the server with an opaque timestamp, requires Capabilities::PING.
+    Ping(u64),
+    // Sent by the client in response to a ping, echoing its timestamp.
+    Pong(u64),
+    // Sent by the client to let the server know what is going on, requires Capabilities::REPORTS.
+    Report(Report),
+    // Sent by the server to offer a datagram channel for lossy events, requires Capabilities::DATAGRAM.
+    Datagram(DatagramSetup),
+    // Like Frame, but numbered so that it can be replayed on a resumed session, requires Capabilities::RESUME.
+    SequencedFrame(u64, Vec<Event>),
+    // Sent by the client to acknowledge all sequenced frames up to and including this one.
+    Ack(u64),
+    // Sent by the client right after hello with the session to resume, if any, requires Capabilities::RESUME.
+    Resume(Option<Resume>),
+    // Sent by the server once the client has been accepted or its session has been resumed.
+    Session(SessionInfo),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Report {
+    // Informational, e.g. the client is ready to inject events.
+    Status(String),
+    // Some events could not be injected, the client carries on.
+    InjectionFailed(String),
+    // The client is about to disconnect because of this error.
+    Error(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hello {
+    // Identifies the client, must be unique among clients connected to a server.
+    pub name: String,
+    pub hostname: String,
+    pub os: String,
+    // Version of the rkvm client.
+    pub version: String,
+    pub screen: Option<Screen>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Screen {
+    pub width: u32,
+    pub height: u32,
 }
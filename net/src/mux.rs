@@ -0,0 +1,483 @@
+use crate::handshake::closed;
+use crate::Error;
+use bytes::{Buf, BytesMut};
+use futures_util::future;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::Notify;
+
+// Bytes a channel may have in flight before the peer grants more by reading them.
+const WINDOW: u32 = 64 * 1024;
+// Writes are split up into chunks of at most this size, which is all a key press
+// ever has to wait for on the connection.
+const MAX_CHUNK: usize = 4 * 1024;
+
+// Every frame starts with the channel (u8), the kind (u8) and a little endian u32,
+// which is the length of the data following it or the number of bytes granted.
+const DATA: u8 = 0;
+const WINDOW_UPDATE: u8 = 1;
+const CLOSE: u8 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    Input,
+    Control,
+    Bulk,
+}
+
+impl Channel {
+    fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Self::Input),
+            1 => Some(Self::Control),
+            2 => Some(Self::Bulk),
+            _ => None,
+        }
+    }
+}
+
+pub struct Channels {
+    pub input: MuxStream,
+    pub control: MuxStream,
+    pub bulk: MuxStream,
+}
+
+// Carries independent channels over a single connection, each with a flow control window of its own,
+// so that a peer not reading one channel doesn't hold up the others.
+//
+// Input is always sent first, the other channels take turns, one chunk at a time.
+// The returned future does the actual I/O and has to be polled for as long as the channels are used.
+// Both peers have to switch over at the same point, e.g. right after the handshake.
+//
+// Only offered by the library for now. There is no capability for it yet, so neither the client
+// nor the server switch over and it isn't part of the wire protocol.
+pub fn multiplex<S>(stream: S) -> (Channels, impl Future<Output = Result<Infallible, Error>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            channels: Default::default(),
+            closed: false,
+        }),
+        notify: Notify::new(),
+    });
+
+    let open = |channel| MuxStream {
+        shared: shared.clone(),
+        channel,
+    };
+
+    let channels = Channels {
+        input: open(Channel::Input),
+        control: open(Channel::Control),
+        bulk: open(Channel::Bulk),
+    };
+
+    let guard = Guard(shared.clone());
+    let driver = async move {
+        let _guard = guard;
+        drive(stream, &shared).await
+    };
+
+    (channels, driver)
+}
+
+// Marks the channels closed once the driver is gone, so that nobody waits for it forever.
+struct Guard(Arc<Shared>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.closed = true;
+
+        for channel in &mut state.channels {
+            wake(&mut channel.reader);
+            wake(&mut channel.writer);
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Wakes up the driver when there's something to send.
+    notify: Notify,
+}
+
+struct State {
+    // Indexed by channel, also in order of priority.
+    channels: [ChannelState; 3],
+    // Set once the driver is gone.
+    closed: bool,
+}
+
+struct ChannelState {
+    // Written by us, but not sent yet.
+    outgoing: BytesMut,
+    // How many more bytes the peer is willing to receive.
+    credit: u32,
+    shutdown: bool,
+    shutdown_sent: bool,
+    // Received, but not read by us yet.
+    incoming: BytesMut,
+    // Read since the peer was last granted more.
+    consumed: u32,
+    eof: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            outgoing: BytesMut::new(),
+            credit: WINDOW,
+            shutdown: false,
+            shutdown_sent: false,
+            incoming: BytesMut::new(),
+            consumed: 0,
+            eof: false,
+            reader: None,
+            writer: None,
+        }
+    }
+}
+
+impl State {
+    // The next frames to send, window updates and closes first, then a single chunk of data.
+    fn take_frames(&mut self, next: &mut usize, frames: &mut Vec<u8>) {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            if channel.consumed >= WINDOW / 2 {
+                put_header(frames, index, WINDOW_UPDATE, channel.consumed);
+                channel.consumed = 0;
+            }
+
+            if channel.shutdown && channel.outgoing.is_empty() && !channel.shutdown_sent {
+                put_header(frames, index, CLOSE, 0);
+                channel.shutdown_sent = true;
+                wake(&mut channel.writer);
+            }
+        }
+
+        let ready = |channel: &ChannelState| !channel.outgoing.is_empty() && channel.credit > 0;
+
+        // Input first, then whichever of the others is next in turn.
+        let index = if ready(&self.channels[0]) {
+            0
+        } else {
+            let candidates = [1 + *next % 2, 1 + (*next + 1) % 2];
+            match candidates
+                .iter()
+                .copied()
+                .find(|index| ready(&self.channels[*index]))
+            {
+                Some(index) => {
+                    *next = index % 2;
+                    index
+                }
+                None => return,
+            }
+        };
+
+        let channel = &mut self.channels[index];
+        let length = channel
+            .outgoing
+            .len()
+            .min(channel.credit as usize)
+            .min(MAX_CHUNK);
+
+        put_header(frames, index, DATA, length as u32);
+        frames.extend_from_slice(&channel.outgoing[..length]);
+        channel.outgoing.advance(length);
+        channel.credit -= length as u32;
+        wake(&mut channel.writer);
+    }
+}
+
+fn put_header(frames: &mut Vec<u8>, channel: usize, kind: u8, value: u32) {
+    frames.push(channel as u8);
+    frames.push(kind);
+    frames.extend_from_slice(&value.to_le_bytes());
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
+
+async fn drive<S>(stream: S, shared: &Shared) -> Result<Infallible, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    future::try_join(receive(&mut reader, shared), send(&mut writer, shared))
+        .await
+        .map(|(never, _)| never)
+}
+
+async fn receive<R>(reader: &mut R, shared: &Shared) -> Result<Infallible, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = vec![0; MAX_CHUNK];
+    loop {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header).await.map_err(closed)?;
+
+        let id = Channel::from_index(header[0])
+            .ok_or_else(|| malformed(format!("Unknown channel {}", header[0])))?;
+        let value = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+
+        match header[1] {
+            DATA => {
+                let length = value as usize;
+                if length > MAX_CHUNK {
+                    return Err(Error::FrameTooLarge {
+                        size: length,
+                        max: MAX_CHUNK as u32,
+                    });
+                }
+
+                reader.read_exact(&mut buffer[..length]).await?;
+
+                let mut state = shared.state.lock().unwrap();
+                let channel = &mut state.channels[id as usize];
+                if channel.incoming.len() + length > WINDOW as usize {
+                    return Err(malformed(format!("{:?} channel window exceeded", id)));
+                }
+
+                channel.incoming.extend_from_slice(&buffer[..length]);
+                wake(&mut channel.reader);
+            }
+            WINDOW_UPDATE => {
+                let mut state = shared.state.lock().unwrap();
+                let channel = &mut state.channels[id as usize];
+                channel.credit = channel
+                    .credit
+                    .checked_add(value)
+                    .filter(|credit| *credit <= WINDOW)
+                    .ok_or_else(|| malformed("Window grown beyond its size".to_owned()))?;
+
+                shared.notify.notify_one();
+            }
+            CLOSE => {
+                let mut state = shared.state.lock().unwrap();
+                let channel = &mut state.channels[id as usize];
+                channel.eof = true;
+                wake(&mut channel.reader);
+            }
+            kind => return Err(malformed(format!("Unknown frame kind {}", kind))),
+        }
+    }
+}
+
+async fn send<W>(writer: &mut W, shared: &Shared) -> Result<Infallible, Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut next = 0;
+    let mut frames = Vec::new();
+    loop {
+        frames.clear();
+        shared
+            .state
+            .lock()
+            .unwrap()
+            .take_frames(&mut next, &mut frames);
+
+        if frames.is_empty() {
+            writer.flush().await?;
+            shared.notify.notified().await;
+            continue;
+        }
+
+        writer.write_all(&frames).await?;
+    }
+}
+
+fn malformed(message: String) -> Error {
+    Error::MalformedFrame(message.into())
+}
+
+// One of the channels, used just like the connection it's multiplexed over.
+pub struct MuxStream {
+    shared: Arc<Shared>,
+    channel: Channel,
+}
+
+impl MuxStream {
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+}
+
+fn gone() -> io::Error {
+    io::Error::new(ErrorKind::BrokenPipe, "Multiplexed connection closed")
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let closed = state.closed;
+        let channel = &mut state.channels[self.channel as usize];
+
+        if !channel.incoming.is_empty() {
+            let count = buf.remaining().min(channel.incoming.len());
+            buf.put_slice(&channel.incoming[..count]);
+            channel.incoming.advance(count);
+
+            channel.consumed += count as u32;
+            if channel.consumed >= WINDOW / 2 {
+                self.shared.notify.notify_one();
+            }
+
+            return Poll::Ready(Ok(()));
+        }
+
+        if channel.eof {
+            return Poll::Ready(Ok(()));
+        }
+
+        if closed {
+            return Poll::Ready(Err(gone()));
+        }
+
+        channel.reader = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Poll::Ready(Err(gone()));
+        }
+
+        let channel = &mut state.channels[self.channel as usize];
+        if channel.shutdown {
+            return Poll::Ready(Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "Channel shut down",
+            )));
+        }
+
+        // Buffer no more than the peer could accept at once.
+        let count = buf.len().min(WINDOW as usize - channel.outgoing.len());
+        if count == 0 {
+            channel.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        channel.outgoing.extend_from_slice(&buf[..count]);
+        self.shared.notify.notify_one();
+
+        Poll::Ready(Ok(count))
+    }
+
+    // Waits until everything written has been handed over to the connection.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let closed = state.closed;
+        let channel = &mut state.channels[self.channel as usize];
+
+        if channel.outgoing.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        if closed {
+            return Poll::Ready(Err(gone()));
+        }
+
+        channel.writer = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    // The peer reads EOF on this channel once everything written before has been sent.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let closed = state.closed;
+        let channel = &mut state.channels[self.channel as usize];
+
+        if channel.shutdown_sent {
+            return Poll::Ready(Ok(()));
+        }
+
+        if closed {
+            return Poll::Ready(Err(gone()));
+        }
+
+        channel.shutdown = true;
+        channel.writer = Some(cx.waker().clone());
+        self.shared.notify.notify_one();
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Framing, Message, PROTOCOL_VERSION};
+
+    fn pair() -> (Channels, Channels) {
+        let (ours, theirs) = tokio::io::duplex(MAX_CHUNK);
+        let (ours, driver) = multiplex(ours);
+        tokio::spawn(driver);
+        let (theirs, driver) = multiplex(theirs);
+        tokio::spawn(driver);
+
+        (ours, theirs)
+    }
+
+    #[tokio::test]
+    async fn input_not_blocked() {
+        let framing = Framing::for_version(PROTOCOL_VERSION).unwrap();
+        let (mut ours, mut theirs) = pair();
+
+        // Far more than fits into the window, nothing is read on the other side yet.
+        let data: Vec<_> = (0..WINDOW * 4).map(|i| i as u8).collect();
+        let expected = data.clone();
+        let mut bulk = ours.bulk;
+        tokio::spawn(async move {
+            bulk.write_all(&data).await.unwrap();
+            bulk.shutdown().await.unwrap();
+        });
+
+        crate::write_message(&mut ours.input, &Message::KeepAlive, &framing)
+            .await
+            .unwrap();
+        let message = crate::read_message(&mut theirs.input, &framing).await;
+        assert!(matches!(message, Ok(Message::KeepAlive)));
+
+        let mut received = Vec::new();
+        theirs.bulk.read_to_end(&mut received).await.unwrap();
+        assert!(received == expected);
+    }
+
+    #[tokio::test]
+    async fn driver_gone() {
+        let (ours, _) = tokio::io::duplex(MAX_CHUNK);
+        let (mut ours, driver) = multiplex(ours);
+        drop(driver);
+
+        let mut buffer = [0; 1];
+        assert!(ours.control.read(&mut buffer).await.is_err());
+        assert!(ours.control.write(&buffer).await.is_err());
+    }
+}
@@ -0,0 +1,512 @@
+use crate::Error;
+use argon2::{Algorithm, Argon2, Params, Version};
+use futures_util::ready;
+use serde::de::{self, Deserialize, Deserializer};
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{Builder, HandshakeState, TransportState};
+use std::convert::TryInto;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+// An alternative to TLS which needs no certificates, only a static key pair on each side and/or a shared passphrase.
+//
+// Both sides exchange their static keys during the handshake (Noise XX), the passphrase is mixed in as a pre-shared
+// key after the client has learned the key of the server, so that a client pinning it doesn't reveal anything
+// derived from the passphrase to anyone else. Each Noise message is prefixed by its length as a big endian u16.
+const PATTERN: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const PSK_PATTERN: &str = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s";
+const PROLOGUE: &[u8] = b"rkvm";
+
+const MAX_MESSAGE_SIZE: usize = u16::MAX as _;
+const TAG_SIZE: usize = 16;
+const MAX_PAYLOAD_SIZE: usize = MAX_MESSAGE_SIZE - TAG_SIZE;
+
+// X25519 private key, written as base64 like WireGuard keys (so wg genkey can be used to generate one).
+#[derive(Clone)]
+pub struct PrivateKey([u8; 32]);
+
+impl PrivateKey {
+    pub fn generate() -> Result<Self, io::Error> {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).map_err(io::Error::from)?;
+
+        Ok(Self(key))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let mut dh = DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .expect("Curve25519 is supported by the default resolver");
+        dh.set(&self.0);
+
+        PublicKey(dh.pubkey().try_into().unwrap())
+    }
+}
+
+// Don't leak the key into logs.
+impl Debug for PrivateKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "PrivateKey(..)")
+    }
+}
+
+impl FromStr for PrivateKey {
+    type Err = &'static str;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        parse_key(data).map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PrivateKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = String::deserialize(deserializer)?;
+        data.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; 32]);
+
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", base64::encode(self.0))
+    }
+}
+
+impl Debug for PublicKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = &'static str;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        parse_key(data).map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = String::deserialize(deserializer)?;
+        data.parse().map_err(de::Error::custom)
+    }
+}
+
+// The pre-shared key derived from a passphrase, once when the config is loaded.
+#[derive(Clone)]
+pub struct Passphrase([u8; 32]);
+
+impl Passphrase {
+    // The passphrase is only as strong as it is long, someone posing as the server can try to guess it offline.
+    // Argon2id with the parameters recommended by OWASP makes that costly, the salt is fixed as both sides have
+    // to arrive at the same key.
+    const SALT: &'static [u8] = b"rkvm noise passphrase";
+    const MEMORY_KIB: u32 = 19 * 1024;
+    const ITERATIONS: u32 = 2;
+}
+
+impl Debug for Passphrase {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Passphrase(..)")
+    }
+}
+
+impl FromStr for Passphrase {
+    type Err = &'static str;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let params = Params::new(Self::MEMORY_KIB, Self::ITERATIONS, 1, Some(32))
+            .map_err(|_| "Invalid key derivation parameters")?;
+
+        let mut psk = [0; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(data.as_bytes(), Self::SALT, &mut psk)
+            .map_err(|_| "Failed to derive a key from the passphrase")?;
+
+        Ok(Self(psk))
+    }
+}
+
+impl<'de> Deserialize<'de> for Passphrase {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = String::deserialize(deserializer)?;
+        data.parse().map_err(de::Error::custom)
+    }
+}
+
+fn parse_key(data: &str) -> Result<[u8; 32], &'static str> {
+    base64::decode(data)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or("Key has to be 32 bytes encoded as base64")
+}
+
+// Carries the protocol over Noise transport messages, each write becomes at least one message.
+pub struct Noise<S> {
+    inner: S,
+    transport: TransportState,
+    remote_key: PublicKey,
+    // The message being read, including its length prefix.
+    incoming: Vec<u8>,
+    received: usize,
+    // Its decrypted payload, handed out to readers.
+    buffer: Vec<u8>,
+    position: usize,
+    // An encrypted message not written in full yet.
+    outgoing: Vec<u8>,
+    sent: usize,
+}
+
+impl<S> Noise<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Performs the client side of the handshake. If the key of the server is known,
+    // the handshake is aborted before anything derived from our key or the passphrase is sent.
+    pub async fn connect(
+        mut stream: S,
+        key: &PrivateKey,
+        passphrase: Option<&Passphrase>,
+        server_key: Option<&PublicKey>,
+    ) -> Result<Self, Error> {
+        let state = builder(key, passphrase)?
+            .build_initiator()
+            .map_err(to_error)?;
+        let transport = handshake(&mut stream, state, server_key).await?;
+
+        Ok(Self::new(stream, transport))
+    }
+
+    // Performs the server side of the handshake, it's up to the caller to check the key of the client.
+    pub async fn accept(
+        mut stream: S,
+        key: &PrivateKey,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<Self, Error> {
+        let state = builder(key, passphrase)?
+            .build_responder()
+            .map_err(to_error)?;
+        let transport = handshake(&mut stream, state, None).await?;
+
+        Ok(Self::new(stream, transport))
+    }
+}
+
+impl<S> Noise<S> {
+    fn new(inner: S, transport: TransportState) -> Self {
+        // XX always transmits the static key of both sides.
+        let remote_key = transport
+            .get_remote_static()
+            .and_then(|key| key.try_into().ok())
+            .map(PublicKey)
+            .expect("Handshake completed without a remote static key");
+
+        Self {
+            inner,
+            transport,
+            remote_key,
+            incoming: vec![0; 2 + MAX_MESSAGE_SIZE],
+            received: 0,
+            buffer: Vec::new(),
+            position: 0,
+            outgoing: Vec::new(),
+            sent: 0,
+        }
+    }
+
+    pub fn remote_key(&self) -> &PublicKey {
+        &self.remote_key
+    }
+}
+
+fn builder<'a>(key: &'a PrivateKey, psk: Option<&'a Passphrase>) -> Result<Builder<'a>, Error> {
+    let pattern = if psk.is_some() { PSK_PATTERN } else { PATTERN };
+    let builder = Builder::new(pattern.parse().map_err(to_error)?)
+        .local_private_key(&key.0)
+        .prologue(PROLOGUE);
+
+    Ok(match psk {
+        Some(psk) => builder.psk(3, &psk.0),
+        None => builder,
+    })
+}
+
+async fn handshake<S>(
+    stream: &mut S,
+    mut state: HandshakeState,
+    expected: Option<&PublicKey>,
+) -> Result<TransportState, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut message = vec![0; 2 + MAX_MESSAGE_SIZE];
+    let mut payload = vec![0; MAX_MESSAGE_SIZE];
+
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            let length = state
+                .write_message(&[], &mut message[2..])
+                .map_err(to_error)?;
+            message[..2].copy_from_slice(&(length as u16).to_be_bytes());
+
+            stream.write_all(&message[..2 + length]).await?;
+            stream.flush().await?;
+
+            continue;
+        }
+
+        let length = stream.read_u16().await? as usize;
+        stream.read_exact(&mut message[..length]).await?;
+        state
+            .read_message(&message[..length], &mut payload)
+            .map_err(to_error)?;
+
+        if let (Some(expected), Some(remote)) = (expected, state.get_remote_static()) {
+            if remote != expected.0 {
+                let remote = PublicKey(remote.try_into().unwrap());
+                return Err(Error::Noise(
+                    format!("Unexpected key {}, expected {}", remote, expected).into(),
+                ));
+            }
+        }
+    }
+
+    state.into_transport_mode().map_err(to_error)
+}
+
+fn to_error(err: snow::Error) -> Error {
+    Error::Noise(err.into())
+}
+
+fn to_io(err: snow::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err)
+}
+
+impl<S> Noise<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        while self.sent < self.outgoing.len() {
+            let count =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.outgoing[self.sent..]))?;
+            if count == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+
+            self.sent += count;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncRead for Noise<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let this = &mut *self;
+        loop {
+            if this.position < this.buffer.len() {
+                let count = buf.remaining().min(this.buffer.len() - this.position);
+                buf.put_slice(&this.buffer[this.position..this.position + count]);
+                this.position += count;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            let length = if this.received < 2 {
+                2
+            } else {
+                2 + u16::from_be_bytes([this.incoming[0], this.incoming[1]]) as usize
+            };
+
+            if this.received < length {
+                let mut read = ReadBuf::new(&mut this.incoming[this.received..length]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+
+                let count = read.filled().len();
+                if count == 0 {
+                    // Closed in between messages.
+                    if this.received == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    return Poll::Ready(Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Truncated Noise message",
+                    )));
+                }
+
+                this.received += count;
+                continue;
+            }
+
+            this.buffer.resize(MAX_MESSAGE_SIZE, 0);
+            let count = this
+                .transport
+                .read_message(&this.incoming[2..length], &mut this.buffer)
+                .map_err(to_io)?;
+            this.buffer.truncate(count);
+            this.position = 0;
+            this.received = 0;
+        }
+    }
+}
+
+impl<S> AsyncWrite for Noise<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = &mut *self;
+        ready!(this.poll_write_outgoing(cx))?;
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let count = buf.len().min(MAX_PAYLOAD_SIZE);
+        this.outgoing.resize(2 + count + TAG_SIZE, 0);
+        let length = this
+            .transport
+            .write_message(&buf[..count], &mut this.outgoing[2..])
+            .map_err(to_io)?;
+        this.outgoing[..2].copy_from_slice(&(length as u16).to_be_bytes());
+        this.outgoing.truncate(2 + length);
+        this.sent = 0;
+
+        Poll::Ready(Ok(count))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        ready!(self.poll_write_outgoing(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        ready!(self.poll_write_outgoing(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    #[test]
+    fn keys() {
+        let key = PrivateKey::generate().unwrap();
+        let public_key = key.public_key();
+        assert_eq!(public_key.to_string().parse(), Ok(public_key));
+
+        assert!("c2hvcnQ=".parse::<PublicKey>().is_err());
+        assert!("not base64".parse::<PrivateKey>().is_err());
+    }
+
+    async fn pair(
+        client_key: &PrivateKey,
+        server_key: &PrivateKey,
+        passphrases: (Option<&str>, Option<&str>),
+        expected: &PublicKey,
+    ) -> (
+        Result<Noise<DuplexStream>, Error>,
+        Result<Noise<DuplexStream>, Error>,
+    ) {
+        let (client, server) = tokio::io::duplex(1024);
+        let client_passphrase = passphrases.0.map(|passphrase| passphrase.parse().unwrap());
+        let server_passphrase = passphrases.1.map(|passphrase| passphrase.parse().unwrap());
+
+        tokio::join!(
+            Noise::connect(
+                client,
+                client_key,
+                client_passphrase.as_ref(),
+                Some(expected)
+            ),
+            Noise::accept(server, server_key, server_passphrase.as_ref()),
+        )
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let client_key = PrivateKey::generate().unwrap();
+        let server_key = PrivateKey::generate().unwrap();
+        let passphrases = (Some("passphrase"), Some("passphrase"));
+        let (client, server) = pair(
+            &client_key,
+            &server_key,
+            passphrases,
+            &server_key.public_key(),
+        )
+        .await;
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        assert_eq!(*client.remote_key(), server_key.public_key());
+        assert_eq!(*server.remote_key(), client_key.public_key());
+
+        // Larger than a single Noise message.
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let write = async {
+            client.write_all(&data).await.unwrap();
+            client.flush().await.unwrap();
+        };
+        let read = async {
+            let mut received = vec![0; data.len()];
+            server.read_exact(&mut received).await.unwrap();
+            received
+        };
+
+        let (_, received) = tokio::join!(write, read);
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn mismatch() {
+        let client_key = PrivateKey::generate().unwrap();
+        let server_key = PrivateKey::generate().unwrap();
+        let expected = server_key.public_key();
+
+        for passphrases in [
+            (Some("passphrase"), Some("other")),
+            (Some("passphrase"), None),
+        ] {
+            let (client, server) = pair(&client_key, &server_key, passphrases, &expected).await;
+            assert!(client.is_err() || server.is_err());
+        }
+
+        let wrong = PrivateKey::generate().unwrap().public_key();
+        let (client, server) = pair(&client_key, &server_key, (None, None), &wrong).await;
+        assert!(matches!(client, Err(Error::Noise(_))));
+        assert!(server.is_err());
+    }
+}
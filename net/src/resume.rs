@@ -0,0 +1,47 @@
+use input::KeyKind;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug, Formatter};
+use std::io::Error;
+
+// A session outlives its connection for a while, so that a client which lost its connection
+// can pick up where it left off:
+//
+// 1. Once a client is accepted, the server sends Message::Session with a ticket.
+//    From then on, frames which must not be lost are sent as Message::SequencedFrame,
+//    numbered from 1, and the client acknowledges them with Message::Ack.
+// 2. When reconnecting, the client sends Message::Resume with the ticket and the last frame it received.
+// 3. The server replays the frames the client missed and sends Message::Session again,
+//    with the keys and buttons it considers pressed. The client releases any others it has pressed,
+//    which is also all of them if the session couldn't be resumed and a new one was started instead.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionTicket([u8; 16]);
+
+impl SessionTicket {
+    pub fn generate() -> Result<Self, Error> {
+        let mut ticket = [0; 16];
+        getrandom::getrandom(&mut ticket).map_err(Error::from)?;
+
+        Ok(Self(ticket))
+    }
+}
+
+// Anyone holding the ticket can take over the session, so keep it out of logs.
+impl Debug for SessionTicket {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "SessionTicket(..)")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Resume {
+    pub ticket: SessionTicket,
+    // The sequence number of the last frame received, 0 if none.
+    pub received: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    pub ticket: SessionTicket,
+    pub pressed: Vec<KeyKind>,
+}
@@ -0,0 +1,320 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+// Address of an AF_VSOCK socket, used to talk to virtual machines without going through their network stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VsockAddr {
+    pub cid: u32,
+    pub port: u32,
+}
+
+impl VsockAddr {
+    // Accepts connections from any virtual machine.
+    pub const CID_ANY: u32 = u32::MAX;
+    // The hypervisor, i.e. the host as seen from a guest.
+    pub const CID_HOST: u32 = 2;
+}
+
+impl Display for VsockAddr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.cid {
+            Self::CID_ANY => write!(f, "vsock:any:{}", self.port),
+            Self::CID_HOST => write!(f, "vsock:host:{}", self.port),
+            cid => write!(f, "vsock:{}:{}", cid, self.port),
+        }
+    }
+}
+
+// Parses CID:PORT, where CID may also be "any" or "host".
+impl FromStr for VsockAddr {
+    type Err = &'static str;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let mut split = data.split(':');
+        let cid = match split.next() {
+            Some("any") => Self::CID_ANY,
+            Some("host") => Self::CID_HOST,
+            Some(cid) => cid.parse().map_err(|_| "Invalid vsock CID")?,
+            None => return Err("Missing vsock CID"),
+        };
+
+        let port = split
+            .next()
+            .ok_or("Missing vsock port")?
+            .parse()
+            .map_err(|_| "Invalid vsock port")?;
+
+        if split.next().is_some() {
+            return Err("Extraneous data");
+        }
+
+        Ok(Self { cid, port })
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{VsockListener, VsockStream};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::VsockAddr;
+    use std::io::{Error, ErrorKind};
+    use std::mem;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    fn socket() -> Result<OwnedFd, Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    fn to_raw(address: VsockAddr) -> libc::sockaddr_vm {
+        let mut raw: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        raw.svm_family = libc::AF_VSOCK as _;
+        raw.svm_cid = address.cid;
+        raw.svm_port = address.port;
+
+        raw
+    }
+
+    fn check(result: libc::c_int) -> Result<(), Error> {
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub struct VsockListener {
+        fd: AsyncFd<OwnedFd>,
+    }
+
+    impl VsockListener {
+        pub fn bind(address: VsockAddr) -> Result<Self, Error> {
+            let fd = socket()?;
+            let raw = to_raw(address);
+
+            check(unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &raw as *const _ as *const libc::sockaddr,
+                    mem::size_of_val(&raw) as _,
+                )
+            })?;
+            check(unsafe { libc::listen(fd.as_raw_fd(), 128) })?;
+
+            Ok(Self {
+                fd: AsyncFd::new(fd)?,
+            })
+        }
+
+        pub async fn accept(&self) -> Result<(VsockStream, VsockAddr), Error> {
+            loop {
+                let mut guard = self.fd.readable().await?;
+                let result = guard.try_io(|fd| {
+                    let mut raw: libc::sockaddr_vm = unsafe { mem::zeroed() };
+                    let mut length = mem::size_of_val(&raw) as libc::socklen_t;
+                    let stream = unsafe {
+                        libc::accept4(
+                            fd.as_raw_fd(),
+                            &mut raw as *mut _ as *mut libc::sockaddr,
+                            &mut length,
+                            libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                        )
+                    };
+
+                    if stream < 0 {
+                        return Err(Error::last_os_error());
+                    }
+
+                    let address = VsockAddr {
+                        cid: raw.svm_cid,
+                        port: raw.svm_port,
+                    };
+
+                    Ok((unsafe { OwnedFd::from_raw_fd(stream) }, address))
+                });
+
+                if let Ok(result) = result {
+                    let (fd, address) = result?;
+                    let stream = VsockStream {
+                        fd: AsyncFd::new(fd)?,
+                    };
+
+                    return Ok((stream, address));
+                }
+            }
+        }
+    }
+
+    pub struct VsockStream {
+        fd: AsyncFd<OwnedFd>,
+    }
+
+    impl VsockStream {
+        pub async fn connect(address: VsockAddr) -> Result<Self, Error> {
+            let fd = socket()?;
+            let raw = to_raw(address);
+
+            let result = check(unsafe {
+                libc::connect(
+                    fd.as_raw_fd(),
+                    &raw as *const _ as *const libc::sockaddr,
+                    mem::size_of_val(&raw) as _,
+                )
+            });
+
+            let fd = AsyncFd::new(fd)?;
+            match result {
+                Ok(()) => {}
+                Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {
+                    // The outcome of a non-blocking connect is known once the socket becomes writable.
+                    let _ = fd.writable().await?;
+
+                    let mut error: libc::c_int = 0;
+                    let mut length = mem::size_of_val(&error) as libc::socklen_t;
+                    check(unsafe {
+                        libc::getsockopt(
+                            fd.as_raw_fd(),
+                            libc::SOL_SOCKET,
+                            libc::SO_ERROR,
+                            &mut error as *mut _ as *mut libc::c_void,
+                            &mut length,
+                        )
+                    })?;
+
+                    if error != 0 {
+                        return Err(Error::from_raw_os_error(error));
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+
+            Ok(Self { fd })
+        }
+    }
+
+    impl AsyncRead for VsockStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<Result<(), Error>> {
+            loop {
+                let mut guard = match self.fd.poll_read_ready(cx) {
+                    Poll::Ready(guard) => guard?,
+                    Poll::Pending => return Poll::Pending,
+                };
+
+                let unfilled = buf.initialize_unfilled();
+                let result = guard.try_io(|fd| {
+                    let count = unsafe {
+                        libc::read(
+                            fd.as_raw_fd(),
+                            unfilled.as_mut_ptr() as *mut libc::c_void,
+                            unfilled.len(),
+                        )
+                    };
+
+                    if count < 0 {
+                        return Err(Error::last_os_error());
+                    }
+
+                    Ok(count as usize)
+                });
+
+                if let Ok(result) = result {
+                    buf.advance(result?);
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+
+    impl AsyncWrite for VsockStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, Error>> {
+            loop {
+                let mut guard = match self.fd.poll_write_ready(cx) {
+                    Poll::Ready(guard) => guard?,
+                    Poll::Pending => return Poll::Pending,
+                };
+
+                let result = guard.try_io(|fd| {
+                    let count = unsafe {
+                        libc::send(
+                            fd.as_raw_fd(),
+                            buf.as_ptr() as *const libc::c_void,
+                            buf.len(),
+                            libc::MSG_NOSIGNAL,
+                        )
+                    };
+
+                    if count < 0 {
+                        return Err(Error::last_os_error());
+                    }
+
+                    Ok(count as usize)
+                });
+
+                if let Ok(result) = result {
+                    return Poll::Ready(result);
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            let result = check(unsafe { libc::shutdown(self.fd.as_raw_fd(), libc::SHUT_WR) });
+            match result {
+                Err(err) if err.kind() == ErrorKind::NotConnected => Poll::Ready(Ok(())),
+                result => Poll::Ready(result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for (data, cid, port) in [
+            ("any:5258", VsockAddr::CID_ANY, 5258),
+            ("host:1", 2, 1),
+            ("3:4", 3, 4),
+        ] {
+            let address: VsockAddr = data.parse().unwrap();
+            assert_eq!(address, VsockAddr { cid, port });
+            assert_eq!(
+                address.to_string(),
+                format!("vsock:{}", data).replace("vsock:2:", "vsock:host:")
+            );
+        }
+
+        for data in ["", "3", "3:", "x:1", "3:4:5"] {
+            assert!(data.parse::<VsockAddr>().is_err(), "{}", data);
+        }
+    }
+}
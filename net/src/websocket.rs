@@ -0,0 +1,121 @@
+use futures_util::{ready, Sink, Stream};
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+// Carries the protocol over binary WebSocket messages, so that it can pass through HTTP proxies.
+// Every write becomes a message of its own, write_message writes each frame in one go.
+pub struct WebSocket<S> {
+    inner: WebSocketStream<S>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<S> WebSocket<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl<S> WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Performs the server side of the HTTP upgrade.
+    pub async fn accept(stream: S) -> Result<Self, Error> {
+        tokio_tungstenite::accept_async(stream)
+            .await
+            .map(Self::new)
+            .map_err(to_io)
+    }
+
+    // Performs the client side of the HTTP upgrade, the URL is only used to build the request.
+    pub async fn connect(stream: S, url: &str) -> Result<Self, Error> {
+        tokio_tungstenite::client_async(url, stream)
+            .await
+            .map(|(stream, _)| Self::new(stream))
+            .map_err(to_io)
+    }
+}
+
+fn to_io(err: WsError) -> Error {
+    match err {
+        WsError::Io(err) => err,
+        err => Error::other(err),
+    }
+}
+
+impl<S> AsyncRead for WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        loop {
+            if self.position < self.buffer.len() {
+                let count = buf.remaining().min(self.buffer.len() - self.position);
+                buf.put_slice(&self.buffer[self.position..self.position + count]);
+                self.position += count;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(WsMessage::Binary(data))) => {
+                    self.buffer = data;
+                    self.position = 0;
+                }
+                Some(Ok(WsMessage::Text(_))) => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Unexpected text message",
+                    )))
+                }
+                // Pings are answered by tungstenite itself.
+                Some(Ok(WsMessage::Ping(_)))
+                | Some(Ok(WsMessage::Pong(_)))
+                | Some(Ok(WsMessage::Frame(_))) => {}
+                Some(Ok(WsMessage::Close(_))) | Some(Err(WsError::ConnectionClosed)) | None => {
+                    return Poll::Ready(Ok(()))
+                }
+                Some(Err(err)) => return Poll::Ready(Err(to_io(err))),
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(to_io)?;
+        Pin::new(&mut self.inner)
+            .start_send(WsMessage::Binary(buf.to_vec()))
+            .map_err(to_io)?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx).map_err(to_io)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_close(cx).map_err(to_io)
+    }
+}
@@ -0,0 +1,991 @@
+use crate::{DatagramSetup, Hello, Message, Report, Resume, SessionInfo, SessionTicket};
+use input::{Axis, Button, Direction, Event, Key, KeyKind, Scroll};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+// What actually goes on the wire. Keys and buttons are sent as their Linux event codes,
+// so that the encoding does not depend on the order in which the variants of input::Key
+// and input::Button are declared.
+//
+// Variants here must only ever be appended, their order is a part of the protocol.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum RawMessage {
+    Event(RawEvent),
+    KeepAlive,
+    Hello(Hello),
+    Rejected(String),
+    Frame(Vec<RawEvent>),
+    Ping(u64),
+    Pong(u64),
+    Report(Report),
+    Datagram(DatagramSetup),
+    SequencedFrame(u64, Vec<RawEvent>),
+    Ack(u64),
+    Resume(Option<Resume>),
+    Session(RawSessionInfo),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RawSessionInfo {
+    ticket: SessionTicket,
+    pressed: Vec<RawKeyKind>,
+}
+
+// Protocol version 1 serialized input::Event as is, that is, keys and buttons by their variant index.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum LegacyMessage {
+    Event(Event),
+    KeepAlive,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum RawEvent {
+    MouseScroll {
+        delta: i32,
+        scroll: Scroll,
+    },
+    MouseMove {
+        axis: Axis,
+        delta: i32,
+    },
+    Key {
+        direction: Direction,
+        kind: RawKeyKind,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum RawKeyKind {
+    Key(u16),
+    Button(u16),
+}
+
+impl From<&Message> for RawMessage {
+    fn from(message: &Message) -> Self {
+        match message {
+            Message::Event(event) => RawMessage::Event((*event).into()),
+            Message::KeepAlive => RawMessage::KeepAlive,
+            Message::Hello(hello) => RawMessage::Hello(hello.clone()),
+            Message::Rejected(reason) => RawMessage::Rejected(reason.clone()),
+            Message::Frame(events) => {
+                RawMessage::Frame(events.iter().copied().map(Into::into).collect())
+            }
+            Message::Ping(timestamp) => RawMessage::Ping(*timestamp),
+            Message::Pong(timestamp) => RawMessage::Pong(*timestamp),
+            Message::Report(report) => RawMessage::Report(report.clone()),
+            Message::Datagram(setup) => RawMessage::Datagram(*setup),
+            Message::SequencedFrame(sequence, events) => RawMessage::SequencedFrame(
+                *sequence,
+                events.iter().copied().map(Into::into).collect(),
+            ),
+            Message::Ack(sequence) => RawMessage::Ack(*sequence),
+            Message::Resume(resume) => RawMessage::Resume(*resume),
+            Message::Session(info) => RawMessage::Session(RawSessionInfo {
+                ticket: info.ticket,
+                pressed: info.pressed.iter().copied().map(Into::into).collect(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<RawMessage> for Message {
+    type Error = UnknownCode;
+
+    fn try_from(message: RawMessage) -> Result<Self, Self::Error> {
+        let message = match message {
+            RawMessage::Event(event) => Message::Event(Event::try_from(event)?),
+            RawMessage::KeepAlive => Message::KeepAlive,
+            RawMessage::Hello(hello) => Message::Hello(hello),
+            RawMessage::Rejected(reason) => Message::Rejected(reason),
+            RawMessage::Frame(events) => Message::Frame(
+                events
+                    .into_iter()
+                    .map(Event::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            RawMessage::Ping(timestamp) => Message::Ping(timestamp),
+            RawMessage::Pong(timestamp) => Message::Pong(timestamp),
+            RawMessage::Report(report) => Message::Report(report),
+            RawMessage::Datagram(setup) => Message::Datagram(setup),
+            RawMessage::SequencedFrame(sequence, events) => Message::SequencedFrame(
+                sequence,
+                events
+                    .into_iter()
+                    .map(Event::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            RawMessage::Ack(sequence) => Message::Ack(sequence),
+            RawMessage::Resume(resume) => Message::Resume(resume),
+            // Keys we don't know about can't have been pressed by us either.
+            RawMessage::Session(info) => Message::Session(SessionInfo {
+                ticket: info.ticket,
+                pressed: info
+                    .pressed
+                    .into_iter()
+                    .filter_map(|kind| KeyKind::try_from(kind).ok())
+                    .collect(),
+            }),
+        };
+
+        Ok(message)
+    }
+}
+
+impl LegacyMessage {
+    // Returns None for messages protocol version 1 did not have.
+    pub(crate) fn new(message: &Message) -> Option<Self> {
+        match message {
+            Message::Event(event) => Some(LegacyMessage::Event(*event)),
+            Message::KeepAlive => Some(LegacyMessage::KeepAlive),
+            _ => None,
+        }
+    }
+}
+
+impl From<LegacyMessage> for Message {
+    fn from(message: LegacyMessage) -> Self {
+        match message {
+            LegacyMessage::Event(event) => Message::Event(event),
+            LegacyMessage::KeepAlive => Message::KeepAlive,
+        }
+    }
+}
+
+impl From<Event> for RawEvent {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::MouseScroll { delta, scroll } => RawEvent::MouseScroll { delta, scroll },
+            Event::MouseMove { axis, delta } => RawEvent::MouseMove { axis, delta },
+            Event::Key { direction, kind } => RawEvent::Key {
+                direction,
+                kind: kind.into(),
+            },
+        }
+    }
+}
+
+impl TryFrom<RawEvent> for Event {
+    type Error = UnknownCode;
+
+    fn try_from(event: RawEvent) -> Result<Self, Self::Error> {
+        let event = match event {
+            RawEvent::MouseScroll { delta, scroll } => Event::MouseScroll { delta, scroll },
+            RawEvent::MouseMove { axis, delta } => Event::MouseMove { axis, delta },
+            RawEvent::Key { direction, kind } => Event::Key {
+                direction,
+                kind: KeyKind::try_from(kind)?,
+            },
+        };
+
+        Ok(event)
+    }
+}
+
+impl From<KeyKind> for RawKeyKind {
+    fn from(kind: KeyKind) -> Self {
+        match kind {
+            KeyKind::Key(key) => RawKeyKind::Key(key.to_code()),
+            KeyKind::Button(button) => RawKeyKind::Button(button.to_code()),
+        }
+    }
+}
+
+impl TryFrom<RawKeyKind> for KeyKind {
+    type Error = UnknownCode;
+
+    fn try_from(kind: RawKeyKind) -> Result<Self, Self::Error> {
+        match kind {
+            RawKeyKind::Key(code) => Key::from_code(code)
+                .map(KeyKind::Key)
+                .ok_or(UnknownCode::Key(code)),
+            RawKeyKind::Button(code) => Button::from_code(code)
+                .map(KeyKind::Button)
+                .ok_or(UnknownCode::Button(code)),
+        }
+    }
+}
+
+// Returned (wrapped in an io::Error) by read_message when the peer sends a key or a button
+// we don't know about, most likely because it runs a newer version of rkvm.
+// The offending frame has been consumed in its entirety, so it is safe to keep reading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownCode {
+    Key(u16),
+    Button(u16),
+}
+
+impl Display for UnknownCode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            UnknownCode::Key(code) => write!(f, "Unknown key code {:#06x}", code),
+            UnknownCode::Button(code) => write!(f, "Unknown button code {:#06x}", code),
+        }
+    }
+}
+
+impl error::Error for UnknownCode {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    // The message layout is bincode's: every enum tag is a little endian u32.
+    #[test]
+    fn message_layout() {
+        let message = Message::Event(Event::Key {
+            direction: Direction::Down,
+            kind: KeyKind::Key(Key::A),
+        });
+
+        let data = bincode::serialize(&RawMessage::from(&message)).unwrap();
+        assert_eq!(
+            data,
+            [0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0x1E, 0x00]
+        );
+
+        let message: RawMessage = bincode::deserialize(&data).unwrap();
+        match Message::try_from(message).unwrap() {
+            Message::Event(Event::Key {
+                direction: Direction::Down,
+                kind: KeyKind::Key(Key::A),
+            }) => {}
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn key_codes() {
+        for (key, code) in KEYS.iter().copied() {
+            assert_eq!(
+                RawKeyKind::from(KeyKind::Key(key)),
+                RawKeyKind::Key(code),
+                "{:?}",
+                key
+            );
+
+            // Aliases decode to the same variant, so compare codes rather than variants.
+            match KeyKind::try_from(RawKeyKind::Key(code)) {
+                Ok(KeyKind::Key(decoded)) => assert_eq!(decoded.to_code(), code, "{:?}", key),
+                result => panic!("{:?} decoded as {:?}", key, result),
+            }
+        }
+    }
+
+    #[test]
+    fn button_codes() {
+        for (button, code) in BUTTONS.iter().copied() {
+            assert_eq!(
+                RawKeyKind::from(KeyKind::Button(button)),
+                RawKeyKind::Button(code),
+                "{:?}",
+                button
+            );
+
+            match KeyKind::try_from(RawKeyKind::Button(code)) {
+                Ok(KeyKind::Button(decoded)) => {
+                    assert_eq!(decoded.to_code(), code, "{:?}", button)
+                }
+                result => panic!("{:?} decoded as {:?}", button, result),
+            }
+        }
+    }
+
+    // Buttons sharing a code, as defined in input-event-codes.h. No other button may share one.
+    const BUTTON_ALIASES: &[&[Button]] = &[
+        &[Button::Misc, Button::N0],
+        &[Button::Mouse, Button::Left],
+        &[Button::Joystick, Button::Trigger],
+        &[Button::Gamepad, Button::South, Button::A],
+        &[Button::East, Button::B],
+        &[Button::North, Button::X],
+        &[Button::West, Button::Y],
+        &[Button::Digi, Button::ToolPen],
+        &[Button::Wheel, Button::GearDown],
+        &[Button::TriggerHappy, Button::TriggerHappy1],
+    ];
+
+    // All variants of a fieldless enum, relying on bincode encoding the variant index as a u32.
+    fn variants<T: serde::de::DeserializeOwned>() -> Vec<T> {
+        (0u32..)
+            .map_while(|index| bincode::deserialize(&index.to_le_bytes()).ok())
+            .collect()
+    }
+
+    #[test]
+    fn button_table() {
+        let buttons = variants::<Button>();
+        assert_eq!(BUTTONS.len(), buttons.len());
+        for button in buttons {
+            assert!(
+                BUTTONS.iter().any(|(listed, _)| *listed == button),
+                "{:?} is missing",
+                button
+            );
+        }
+
+        let mut codes = HashMap::<_, HashSet<_>>::new();
+        for (button, code) in BUTTONS.iter().copied() {
+            codes.entry(code).or_default().insert(button);
+        }
+
+        for (code, buttons) in codes {
+            let alias = BUTTON_ALIASES
+                .iter()
+                .any(|aliases| aliases.iter().copied().collect::<HashSet<_>>() == buttons);
+            assert!(
+                buttons.len() == 1 || alias,
+                "{:?} share the code {:#06x}",
+                buttons,
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_codes() {
+        assert_eq!(
+            KeyKind::try_from(RawKeyKind::Key(0xFFFF)),
+            Err(UnknownCode::Key(0xFFFF))
+        );
+        assert_eq!(
+            KeyKind::try_from(RawKeyKind::Button(0x0000)),
+            Err(UnknownCode::Button(0x0000))
+        );
+    }
+
+    // Pressed keys we don't know about are dropped rather than failing the whole message.
+    #[test]
+    fn session_info() {
+        let ticket = SessionTicket::generate().unwrap();
+        let message = RawMessage::Session(RawSessionInfo {
+            ticket,
+            pressed: vec![RawKeyKind::Key(0x001E), RawKeyKind::Key(0xFFFF)],
+        });
+
+        let data = bincode::serialize(&message).unwrap();
+        let message: RawMessage = bincode::deserialize(&data).unwrap();
+        match Message::try_from(message).unwrap() {
+            Message::Session(info) => {
+                assert!(info.ticket == ticket);
+                assert_eq!(info.pressed, [KeyKind::Key(Key::A)]);
+            }
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    const KEYS: &[(Key, u16)] = &[
+        (Key::A, 0x001E),
+        (Key::Ab, 0x0196),
+        (Key::AddressBook, 0x01AD),
+        (Key::Again, 0x0081),
+        (Key::AlsToggle, 0x0230),
+        (Key::AltErase, 0x00DE),
+        (Key::Angle, 0x0173),
+        (Key::Apostrophe, 0x0028),
+        (Key::Appselect, 0x0244),
+        (Key::Archive, 0x0169),
+        (Key::AspectRatio, 0x0177),
+        (Key::Assistant, 0x0247),
+        (Key::AttendantOff, 0x021C),
+        (Key::AttendantOn, 0x021B),
+        (Key::AttendantToggle, 0x021D),
+        (Key::Audio, 0x0188),
+        (Key::AudioDesc, 0x026E),
+        (Key::Aux, 0x0186),
+        (Key::B, 0x0030),
+        (Key::Back, 0x009E),
+        (Key::Backslash, 0x002B),
+        (Key::Backspace, 0x000E),
+        (Key::BassBoost, 0x00D1),
+        (Key::Battery, 0x00EC),
+        (Key::Blue, 0x0191),
+        (Key::Bluetooth, 0x00ED),
+        (Key::Bookmarks, 0x009C),
+        (Key::Break, 0x019B),
+        (Key::BrightnessAuto, 0x00F4),
+        (Key::BrightnessCycle, 0x00F3),
+        (Key::BrightnessMax, 0x0251),
+        (Key::BrightnessMin, 0x0250),
+        (Key::BrightnessToggle, 0x01AF),
+        (Key::BrightnessZero, 0x00F4),
+        (Key::BrightnessDown, 0x00E0),
+        (Key::BrightnessUp, 0x00E1),
+        (Key::BrlDot1, 0x01F1),
+        (Key::BrlDot10, 0x01FA),
+        (Key::BrlDot2, 0x01F2),
+        (Key::BrlDot3, 0x01F3),
+        (Key::BrlDot4, 0x01F4),
+        (Key::BrlDot5, 0x01F5),
+        (Key::BrlDot6, 0x01F6),
+        (Key::BrlDot7, 0x01F7),
+        (Key::BrlDot8, 0x01F8),
+        (Key::BrlDot9, 0x01F9),
+        (Key::ButtonConfig, 0x0240),
+        (Key::C, 0x002E),
+        (Key::Calc, 0x008C),
+        (Key::Calendar, 0x018D),
+        (Key::Camera, 0x00D4),
+        (Key::CameraDown, 0x0218),
+        (Key::CameraFocus, 0x0210),
+        (Key::CameraLeft, 0x0219),
+        (Key::CameraRight, 0x021A),
+        (Key::CameraUp, 0x0217),
+        (Key::CameraZoomIn, 0x0215),
+        (Key::CameraZoomOut, 0x0216),
+        (Key::Cancel, 0x00DF),
+        (Key::CapsLock, 0x003A),
+        (Key::Cd, 0x017F),
+        (Key::Channel, 0x016B),
+        (Key::ChannelDown, 0x0193),
+        (Key::ChannelUp, 0x0192),
+        (Key::Chat, 0x00D8),
+        (Key::Clear, 0x0163),
+        (Key::Close, 0x00CE),
+        (Key::CloseCd, 0x00A0),
+        (Key::Coffee, 0x0098),
+        (Key::Comma, 0x0033),
+        (Key::Compose, 0x007F),
+        (Key::Computer, 0x009D),
+        (Key::Config, 0x00AB),
+        (Key::Connect, 0x00DA),
+        (Key::ContextMenu, 0x01B6),
+        (Key::Controlpanel, 0x0243),
+        (Key::Copy, 0x0085),
+        (Key::Cut, 0x0089),
+        (Key::CycleWindows, 0x009A),
+        (Key::D, 0x0020),
+        (Key::Dashboard, 0x00CC),
+        (Key::Data, 0x0277),
+        (Key::Database, 0x01AA),
+        (Key::DelEol, 0x01C0),
+        (Key::DelEos, 0x01C1),
+        (Key::DelLine, 0x01C3),
+        (Key::Delete, 0x006F),
+        (Key::DeleteFile, 0x0092),
+        (Key::Digits, 0x019D),
+        (Key::Direction, 0x0099),
+        (Key::Directory, 0x018A),
+        (Key::DisplayOff, 0x00F5),
+        (Key::DisplayToggle, 0x01AF),
+        (Key::Documents, 0x00EB),
+        (Key::Dollar, 0x01B2),
+        (Key::Dot, 0x0034),
+        (Key::Down, 0x006C),
+        (Key::Dvd, 0x0185),
+        (Key::E, 0x0012),
+        (Key::Edit, 0x00B0),
+        (Key::Editor, 0x01A6),
+        (Key::EjectCd, 0x00A1),
+        (Key::EjectCloseCd, 0x00A2),
+        (Key::Email, 0x00D7),
+        (Key::End, 0x006B),
+        (Key::Enter, 0x001C),
+        (Key::Epg, 0x016D),
+        (Key::Equal, 0x000D),
+        (Key::Esc, 0x0001),
+        (Key::Euro, 0x01B3),
+        (Key::Exit, 0x00AE),
+        (Key::F, 0x0021),
+        (Key::F1, 0x003B),
+        (Key::F10, 0x0044),
+        (Key::F11, 0x0057),
+        (Key::F12, 0x0058),
+        (Key::F13, 0x00B7),
+        (Key::F14, 0x00B8),
+        (Key::F15, 0x00B9),
+        (Key::F16, 0x00BA),
+        (Key::F17, 0x00BB),
+        (Key::F18, 0x00BC),
+        (Key::F19, 0x00BD),
+        (Key::F2, 0x003C),
+        (Key::F20, 0x00BE),
+        (Key::F21, 0x00BF),
+        (Key::F22, 0x00C0),
+        (Key::F23, 0x00C1),
+        (Key::F24, 0x00C2),
+        (Key::F3, 0x003D),
+        (Key::F4, 0x003E),
+        (Key::F5, 0x003F),
+        (Key::F6, 0x0040),
+        (Key::F7, 0x0041),
+        (Key::F8, 0x0042),
+        (Key::F9, 0x0043),
+        (Key::FastForward, 0x00D0),
+        (Key::FastReverse, 0x0275),
+        (Key::Favorites, 0x016C),
+        (Key::File, 0x0090),
+        (Key::Finance, 0x00DB),
+        (Key::Find, 0x0088),
+        (Key::First, 0x0194),
+        (Key::Fn, 0x01D0),
+        (Key::Fn1, 0x01DE),
+        (Key::Fn2, 0x01DF),
+        (Key::FnB, 0x01E4),
+        (Key::FnD, 0x01E0),
+        (Key::FnE, 0x01E1),
+        (Key::FnEsc, 0x01D1),
+        (Key::FnF, 0x01E2),
+        (Key::FnF1, 0x01D2),
+        (Key::FnF10, 0x01DB),
+        (Key::FnF11, 0x01DC),
+        (Key::FnF12, 0x01DD),
+        (Key::FnF2, 0x01D3),
+        (Key::FnF3, 0x01D4),
+        (Key::FnF4, 0x01D5),
+        (Key::FnF5, 0x01D6),
+        (Key::FnF6, 0x01D7),
+        (Key::FnF7, 0x01D8),
+        (Key::FnF8, 0x01D9),
+        (Key::FnF9, 0x01DA),
+        (Key::FnS, 0x01E3),
+        (Key::Forward, 0x009F),
+        (Key::ForwardMail, 0x00E9),
+        (Key::Frameback, 0x01B4),
+        (Key::FrameForward, 0x01B5),
+        (Key::Front, 0x0084),
+        (Key::FullScreen, 0x0174),
+        (Key::G, 0x0022),
+        (Key::Games, 0x01A1),
+        (Key::Goto, 0x0162),
+        (Key::GraphicsEditor, 0x01A8),
+        (Key::Grave, 0x0029),
+        (Key::Green, 0x018F),
+        (Key::H, 0x0023),
+        (Key::Hangeul, 0x007A),
+        (Key::Hanja, 0x007B),
+        (Key::Help, 0x008A),
+        (Key::Henkan, 0x005C),
+        (Key::Hiragana, 0x005B),
+        (Key::Home, 0x0066),
+        (Key::Homepage, 0x00AC),
+        (Key::Hp, 0x00D3),
+        (Key::I, 0x0017),
+        (Key::Images, 0x01BA),
+        (Key::Info, 0x0166),
+        (Key::InsLine, 0x01C2),
+        (Key::Insert, 0x006E),
+        (Key::Iso, 0x00AA),
+        (Key::J, 0x0024),
+        (Key::Journal, 0x0242),
+        (Key::K, 0x0025),
+        (Key::Katakana, 0x005A),
+        (Key::KatakanaHiragana, 0x005D),
+        (Key::KbdLayoutNext, 0x0248),
+        (Key::KbdLcdMenu1, 0x02B8),
+        (Key::KbdLcdMenu2, 0x02B9),
+        (Key::KbdLcdMenu3, 0x02BA),
+        (Key::KbdLcdMenu4, 0x02BB),
+        (Key::KbdLcdMenu5, 0x02BC),
+        (Key::KbdIllumDown, 0x00E5),
+        (Key::KbdIllumToggle, 0x00E4),
+        (Key::KbdIllumUp, 0x00E6),
+        (Key::KbdInputAssistAccept, 0x0264),
+        (Key::KbdInputAssistCancel, 0x0265),
+        (Key::KbdInputAssistNext, 0x0261),
+        (Key::KbdInputAssistNextgroup, 0x0263),
+        (Key::KbdInputAssistPrev, 0x0260),
+        (Key::KbdInputAssistPrevgroup, 0x0262),
+        (Key::Keyboard, 0x0176),
+        (Key::Kp0, 0x0052),
+        (Key::Kp1, 0x004F),
+        (Key::Kp2, 0x0050),
+        (Key::Kp3, 0x0051),
+        (Key::Kp4, 0x004B),
+        (Key::Kp5, 0x004C),
+        (Key::Kp6, 0x004D),
+        (Key::Kp7, 0x0047),
+        (Key::Kp8, 0x0048),
+        (Key::Kp9, 0x0049),
+        (Key::KpAsterisk, 0x0037),
+        (Key::KpComma, 0x0079),
+        (Key::KpDott, 0x0053),
+        (Key::KpEnter, 0x0060),
+        (Key::KpEqual, 0x0075),
+        (Key::KpJpComma, 0x005F),
+        (Key::KpLeftParen, 0x00B3),
+        (Key::KpMinus, 0x004A),
+        (Key::KpPlus, 0x004E),
+        (Key::KpPlusMinus, 0x0076),
+        (Key::KpRightParen, 0x00B4),
+        (Key::KpSlash, 0x0062),
+        (Key::L, 0x0026),
+        (Key::Language, 0x0170),
+        (Key::Last, 0x0195),
+        (Key::Left, 0x0069),
+        (Key::LeftDown, 0x0269),
+        (Key::LeftUp, 0x0268),
+        (Key::LeftAlt, 0x0038),
+        (Key::LeftBrace, 0x001A),
+        (Key::LeftCtrl, 0x001D),
+        (Key::LeftMeta, 0x007D),
+        (Key::LeftShift, 0x002A),
+        (Key::LightsToggle, 0x021E),
+        (Key::LineFeed, 0x0065),
+        (Key::List, 0x018B),
+        (Key::LogOff, 0x01B1),
+        (Key::M, 0x0032),
+        (Key::Macro, 0x0070),
+        (Key::Macro1, 0x0290),
+        (Key::Macro10, 0x0299),
+        (Key::Macro11, 0x029A),
+        (Key::Macro12, 0x029B),
+        (Key::Macro13, 0x029C),
+        (Key::Macro14, 0x029D),
+        (Key::Macro15, 0x029E),
+        (Key::Macro16, 0x029F),
+        (Key::Macro17, 0x02A0),
+        (Key::Macro18, 0x02A1),
+        (Key::Macro19, 0x02A2),
+        (Key::Macro2, 0x0291),
+        (Key::Macro20, 0x02A3),
+        (Key::Macro21, 0x02A4),
+        (Key::Macro22, 0x02A5),
+        (Key::Macro23, 0x02A6),
+        (Key::Macro24, 0x02A7),
+        (Key::Macro25, 0x02A8),
+        (Key::Macro26, 0x02A9),
+        (Key::Macro27, 0x02AA),
+        (Key::Macro28, 0x02AB),
+        (Key::Macro29, 0x02AC),
+        (Key::Macro3, 0x0292),
+        (Key::Macro30, 0x02AD),
+        (Key::Macro4, 0x0293),
+        (Key::Macro5, 0x0294),
+        (Key::Macro6, 0x0295),
+        (Key::Macro7, 0x0296),
+        (Key::Macro8, 0x0297),
+        (Key::Macro9, 0x0298),
+        (Key::MacroPreset1, 0x02B3),
+        (Key::MacroPreset2, 0x02B4),
+        (Key::MacroPreset3, 0x02B5),
+        (Key::MacroPresetCycle, 0x02B2),
+        (Key::MacroRecordStart, 0x02B0),
+        (Key::MacroRecordStop, 0x02B1),
+        (Key::Mail, 0x009B),
+        (Key::Media, 0x00E2),
+        (Key::MediaRepeat, 0x01B7),
+        (Key::MediaTopMenu, 0x026B),
+        (Key::Memo, 0x018C),
+        (Key::Menu, 0x008B),
+        (Key::Messenger, 0x01AE),
+        (Key::Mhp, 0x016F),
+        (Key::MicMute, 0x00F8),
+        (Key::Minus, 0x000C),
+        (Key::Mode, 0x0175),
+        (Key::Move, 0x00AF),
+        (Key::Mp3, 0x0187),
+        (Key::MsDos, 0x0097),
+        (Key::Muhenkan, 0x005E),
+        (Key::Mute, 0x0071),
+        (Key::N, 0x0031),
+        (Key::N0, 0x000B),
+        (Key::N1, 0x0002),
+        (Key::N102nd, 0x0056),
+        (Key::N10ChannelsDown, 0x01B9),
+        (Key::N10ChannelsUp, 0x01B8),
+        (Key::N2, 0x0003),
+        (Key::N3, 0x0004),
+        (Key::N3dMode, 0x026F),
+        (Key::N4, 0x0005),
+        (Key::N5, 0x0006),
+        (Key::N6, 0x0007),
+        (Key::N7, 0x0008),
+        (Key::N8, 0x0009),
+        (Key::N9, 0x000A),
+        (Key::New, 0x00B5),
+        (Key::News, 0x01AB),
+        (Key::Next, 0x0197),
+        (Key::NextFavorite, 0x0270),
+        (Key::NextSong, 0x00A3),
+        (Key::Numeric0, 0x0200),
+        (Key::Numeric1, 0x0201),
+        (Key::Numeric11, 0x026C),
+        (Key::Numeric12, 0x026D),
+        (Key::Numeric2, 0x0202),
+        (Key::Numeric3, 0x0203),
+        (Key::Numeric4, 0x0204),
+        (Key::Numeric5, 0x0205),
+        (Key::Numeric6, 0x0206),
+        (Key::Numeric7, 0x0207),
+        (Key::Numeric8, 0x0208),
+        (Key::Numeric9, 0x0209),
+        (Key::NumericA, 0x020C),
+        (Key::NumericB, 0x020D),
+        (Key::NumericC, 0x020E),
+        (Key::NumericD, 0x020F),
+        (Key::NumericPound, 0x020B),
+        (Key::NumericStar, 0x020A),
+        (Key::NumLock, 0x0045),
+        (Key::O, 0x0018),
+        (Key::Ok, 0x0160),
+        (Key::OnscreenKeyboard, 0x0278),
+        (Key::Open, 0x0086),
+        (Key::Option, 0x0165),
+        (Key::P, 0x0019),
+        (Key::PageDown, 0x006D),
+        (Key::PageUp, 0x0068),
+        (Key::Paste, 0x0087),
+        (Key::Pause, 0x0077),
+        (Key::PauseRecord, 0x0272),
+        (Key::PauseCd, 0x00C9),
+        (Key::Pc, 0x0178),
+        (Key::Phone, 0x00A9),
+        (Key::Play, 0x00CF),
+        (Key::PlayCd, 0x00C8),
+        (Key::Player, 0x0183),
+        (Key::PlayPause, 0x00A4),
+        (Key::Power, 0x0074),
+        (Key::Power2, 0x0164),
+        (Key::Presentation, 0x01A9),
+        (Key::Previous, 0x019C),
+        (Key::PreviousSong, 0x00A5),
+        (Key::Print, 0x00D2),
+        (Key::PrivacyScreenToggle, 0x0279),
+        (Key::Prog1, 0x0094),
+        (Key::Prog2, 0x0095),
+        (Key::Prog3, 0x00CA),
+        (Key::Prog4, 0x00CB),
+        (Key::Program, 0x016A),
+        (Key::Props, 0x0082),
+        (Key::Pvr, 0x016E),
+        (Key::Q, 0x0010),
+        (Key::Question, 0x00D6),
+        (Key::R, 0x0013),
+        (Key::Radio, 0x0181),
+        (Key::Record, 0x00A7),
+        (Key::Red, 0x018E),
+        (Key::Redo, 0x00B6),
+        (Key::Refresh, 0x00AD),
+        (Key::Reply, 0x00E8),
+        (Key::Reserved, 0x0000),
+        (Key::Restart, 0x0198),
+        (Key::Rewind, 0x00A8),
+        (Key::RfKill, 0x00F7),
+        (Key::Right, 0x006A),
+        (Key::RightDown, 0x0267),
+        (Key::RightUp, 0x0266),
+        (Key::RightAlt, 0x0064),
+        (Key::RightBrace, 0x001B),
+        (Key::RightCtrl, 0x0061),
+        (Key::RightMeta, 0x007E),
+        (Key::RightShift, 0x0036),
+        (Key::Ro, 0x0059),
+        (Key::RootMenu, 0x026A),
+        (Key::RotateDisplay, 0x0099),
+        (Key::RotateLockToggle, 0x0231),
+        (Key::S, 0x001F),
+        (Key::Sat, 0x017D),
+        (Key::Sat2, 0x017E),
+        (Key::Save, 0x00EA),
+        (Key::Scale, 0x0078),
+        (Key::Screen, 0x0177),
+        (Key::Screenlock, 0x0098),
+        (Key::Screensaver, 0x0245),
+        (Key::ScrollDown, 0x00B2),
+        (Key::ScrollLock, 0x0046),
+        (Key::ScrollUp, 0x00B1),
+        (Key::Search, 0x00D9),
+        (Key::Select, 0x0161),
+        (Key::SelectiveScreenshot, 0x027A),
+        (Key::Semicolon, 0x0027),
+        (Key::Send, 0x00E7),
+        (Key::SendFile, 0x0091),
+        (Key::Setup, 0x008D),
+        (Key::Shop, 0x00DD),
+        (Key::Shuffle, 0x019A),
+        (Key::Slash, 0x0035),
+        (Key::Sleep, 0x008E),
+        (Key::Slow, 0x0199),
+        (Key::SlowReverse, 0x0276),
+        (Key::Sound, 0x00D5),
+        (Key::Space, 0x0039),
+        (Key::Spellcheck, 0x01B0),
+        (Key::Sport, 0x00DC),
+        (Key::Spreadsheet, 0x01A7),
+        (Key::Stop, 0x0080),
+        (Key::StopRecord, 0x0271),
+        (Key::StopCd, 0x00A6),
+        (Key::Subtitle, 0x0172),
+        (Key::Suspend, 0x00CD),
+        (Key::SwitchVideoMode, 0x00E3),
+        (Key::SysRq, 0x0063),
+        (Key::T, 0x0014),
+        (Key::Tab, 0x000F),
+        (Key::Tape, 0x0180),
+        (Key::TaskManager, 0x0241),
+        (Key::Teen, 0x019E),
+        (Key::Text, 0x0184),
+        (Key::Time, 0x0167),
+        (Key::Title, 0x0171),
+        (Key::TouchpadOff, 0x0214),
+        (Key::TouchpadOn, 0x0213),
+        (Key::TouchpadToggle, 0x0212),
+        (Key::Tuner, 0x0182),
+        (Key::Tv, 0x0179),
+        (Key::Tv2, 0x017A),
+        (Key::Twen, 0x019F),
+        (Key::U, 0x0016),
+        (Key::Undo, 0x0083),
+        (Key::Unknown, 0x00F0),
+        (Key::Unmute, 0x0274),
+        (Key::Up, 0x0067),
+        (Key::Uwb, 0x00EF),
+        (Key::V, 0x002F),
+        (Key::Vcr, 0x017B),
+        (Key::Vcr2, 0x017C),
+        (Key::Vendor, 0x0168),
+        (Key::Video, 0x0189),
+        (Key::VideoNext, 0x00F1),
+        (Key::VideoPrev, 0x00F2),
+        (Key::VideoPhone, 0x01A0),
+        (Key::Vod, 0x0273),
+        (Key::VoiceCommand, 0x0246),
+        (Key::VoiceMail, 0x01AC),
+        (Key::VolumeDown, 0x0072),
+        (Key::VolumeUp, 0x0073),
+        (Key::W, 0x0011),
+        (Key::WakeUp, 0x008F),
+        (Key::Wimax, 0x00F6),
+        (Key::Wlan, 0x00EE),
+        (Key::WordProcessor, 0x01A5),
+        (Key::WpsButton, 0x0211),
+        (Key::Wwan, 0x00F6),
+        (Key::Www, 0x0096),
+        (Key::X, 0x002D),
+        (Key::Xfer, 0x0093),
+        (Key::Y, 0x0015),
+        (Key::Yellow, 0x0190),
+        (Key::Yen, 0x007C),
+        (Key::Z, 0x002C),
+        (Key::ZenkakuHankaku, 0x0055),
+        (Key::Zoom, 0x0174),
+        (Key::ZoomIn, 0x01A2),
+        (Key::ZoomOut, 0x01A3),
+        (Key::ZoomReset, 0x01A4),
+    ];
+
+    const BUTTONS: &[(Button, u16)] = &[
+        (Button::A, 0x0130),
+        (Button::B, 0x0131),
+        (Button::Back, 0x0116),
+        (Button::Base, 0x0126),
+        (Button::Base2, 0x0127),
+        (Button::Base3, 0x0128),
+        (Button::Base4, 0x0129),
+        (Button::Base5, 0x012A),
+        (Button::Base6, 0x012B),
+        (Button::C, 0x0132),
+        (Button::Dead, 0x012F),
+        (Button::Digi, 0x0140),
+        (Button::DpadDown, 0x0221),
+        (Button::DpadLeft, 0x0222),
+        (Button::DpadRight, 0x0223),
+        (Button::DpadUp, 0x0220),
+        (Button::East, 0x0131),
+        (Button::Extra, 0x0114),
+        (Button::Forward, 0x0115),
+        (Button::Gamepad, 0x0130),
+        (Button::GearDown, 0x0150),
+        (Button::GearUp, 0x0151),
+        (Button::Joystick, 0x0120),
+        (Button::Left, 0x0110),
+        (Button::Middle, 0x0112),
+        (Button::Misc, 0x0100),
+        (Button::Mode, 0x013C),
+        (Button::Mouse, 0x0110),
+        (Button::N0, 0x0100),
+        (Button::N1, 0x0101),
+        (Button::N2, 0x0102),
+        (Button::N3, 0x0103),
+        (Button::N4, 0x0104),
+        (Button::N5, 0x0105),
+        (Button::N6, 0x0106),
+        (Button::N7, 0x0107),
+        (Button::N8, 0x0108),
+        (Button::N9, 0x0109),
+        (Button::North, 0x0133),
+        (Button::Pinkie, 0x0125),
+        (Button::Right, 0x0111),
+        (Button::Select, 0x013A),
+        (Button::Side, 0x0113),
+        (Button::South, 0x0130),
+        (Button::Start, 0x013B),
+        (Button::Stylus, 0x014B),
+        (Button::Stylus2, 0x014C),
+        (Button::Stylus3, 0x0149),
+        (Button::Task, 0x0117),
+        (Button::Thumb, 0x0121),
+        (Button::Thumb2, 0x0122),
+        (Button::Thumbl, 0x013D),
+        (Button::Thumbr, 0x013E),
+        (Button::Tl, 0x0136),
+        (Button::Tl2, 0x0138),
+        (Button::ToolAirbrush, 0x0144),
+        (Button::ToolBrush, 0x0142),
+        (Button::ToolDoubletap, 0x014D),
+        (Button::ToolFinger, 0x0145),
+        (Button::ToolLens, 0x0147),
+        (Button::ToolMouse, 0x0146),
+        (Button::ToolPen, 0x0140),
+        (Button::ToolPencil, 0x0143),
+        (Button::ToolQuadtap, 0x014F),
+        (Button::ToolQuinttap, 0x0148),
+        (Button::ToolRubber, 0x0141),
+        (Button::ToolTripletap, 0x014E),
+        (Button::Top, 0x0123),
+        (Button::Top2, 0x0124),
+        (Button::Touch, 0x014A),
+        (Button::Tr, 0x0137),
+        (Button::Tr2, 0x0139),
+        (Button::Trigger, 0x0120),
+        (Button::TriggerHappy, 0x02C0),
+        (Button::TriggerHappy1, 0x02C0),
+        (Button::TriggerHappy10, 0x02C9),
+        (Button::TriggerHappy11, 0x02CA),
+        (Button::TriggerHappy12, 0x02CB),
+        (Button::TriggerHappy13, 0x02CC),
+        (Button::TriggerHappy14, 0x02CD),
+        (Button::TriggerHappy15, 0x02CE),
+        (Button::TriggerHappy16, 0x02CF),
+        (Button::TriggerHappy17, 0x02D0),
+        (Button::TriggerHappy18, 0x02D1),
+        (Button::TriggerHappy19, 0x02D2),
+        (Button::TriggerHappy2, 0x02C1),
+        (Button::TriggerHappy20, 0x02D3),
+        (Button::TriggerHappy21, 0x02D4),
+        (Button::TriggerHappy22, 0x02D5),
+        (Button::TriggerHappy23, 0x02D6),
+        (Button::TriggerHappy24, 0x02D7),
+        (Button::TriggerHappy25, 0x02D8),
+        (Button::TriggerHappy26, 0x02D9),
+        (Button::TriggerHappy27, 0x02DA),
+        (Button::TriggerHappy28, 0x02DB),
+        (Button::TriggerHappy29, 0x02DC),
+        (Button::TriggerHappy3, 0x02C2),
+        (Button::TriggerHappy30, 0x02DD),
+        (Button::TriggerHappy31, 0x02DE),
+        (Button::TriggerHappy32, 0x02DF),
+        (Button::TriggerHappy33, 0x02E0),
+        (Button::TriggerHappy34, 0x02E1),
+        (Button::TriggerHappy35, 0x02E2),
+        (Button::TriggerHappy36, 0x02E3),
+        (Button::TriggerHappy37, 0x02E4),
+        (Button::TriggerHappy38, 0x02E5),
+        (Button::TriggerHappy39, 0x02E6),
+        (Button::TriggerHappy4, 0x02C3),
+        (Button::TriggerHappy40, 0x02E7),
+        (Button::TriggerHappy5, 0x02C4),
+        (Button::TriggerHappy6, 0x02C5),
+        (Button::TriggerHappy7, 0x02C6),
+        (Button::TriggerHappy8, 0x02C7),
+        (Button::TriggerHappy9, 0x02C8),
+        (Button::West, 0x0134),
+        (Button::Wheel, 0x0150),
+        (Button::X, 0x0133),
+        (Button::Y, 0x0134),
+        (Button::Z, 0x0135),
+    ];
+}
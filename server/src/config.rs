@@ -1,15 +1,181 @@
 use input::Key;
-use serde::Deserialize;
-use std::collections::HashSet;
+use net::{Fingerprint, KeepAliveConfig, Passphrase, PrivateKey, PublicKey, VsockAddr};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
-    pub listen_address: SocketAddr,
+    // May be left unset if the server only dials out to clients.
+    pub listen_address: Option<ListenAddress>,
     pub switch_keys: HashSet<Key>,
-    pub identity_path: PathBuf,
+    // Names of clients in the order they are switched to.
+    #[serde(default)]
+    pub clients: Vec<String>,
+    #[serde(default)]
+    pub transport: Transport,
+    // Send mouse movement and scrolling over UDP on the same address as well.
+    #[serde(default)]
+    pub datagram: bool,
+    // Required unless listening on a Unix or vsock socket, which use TLS only if this or the PEM files
+    // below are set, or using Noise.
+    pub identity_path: Option<PathBuf>,
     #[serde(default)]
     pub identity_password: String,
+    // PEM files as written by rkvm-certificate-gen, used instead of identity-path if set.
+    pub certificate_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    // Only let in clients presenting a known certificate.
+    pub client_auth: Option<ClientAuth>,
+    // Used by the noise transport instead of certificates.
+    pub noise: Option<Noise>,
+    // Permissions of a Unix listen socket, e.g. 0o660, left to the umask if unset.
+    pub socket_mode: Option<u32>,
+    // Virtual machines allowed to connect to a vsock listen address, by CID.
+    // A vsock listen address requires either this or client-auth.
+    pub vsock_cids: Option<HashSet<u32>>,
+    // Clients in reverse-connect mode, which listen for the server to connect to them.
+    #[serde(default)]
+    pub dial: HashMap<String, Dial>,
+    #[serde(default)]
+    pub keep_alive: KeepAliveConfig,
+    // How often to log the round trip time and jitter of each client, in seconds. Defaults to 60, 0 disables it.
+    pub status_interval_secs: Option<u64>,
+    // Largest frame sent to or accepted from clients, in bytes. Defaults to 65536.
+    pub max_frame_size: Option<u32>,
+}
+
+// Client certificates are accepted if listed by fingerprint or issued by the CA.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ClientAuth {
+    // PEM file with the CA certificate(s), the client goes by the common name of the certificate subject.
+    pub ca_path: Option<PathBuf>,
+    // Client names mapped to the SHA-256 fingerprints of their certificates.
+    #[serde(default)]
+    pub fingerprints: HashMap<String, Fingerprint>,
+}
+
+// Clients are let in if their key is listed, if they know the passphrase, or both if both are set.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Noise {
+    // Generated on every start if unset, clients can't pin the key of the server then.
+    pub private_key: Option<PrivateKey>,
+    pub passphrase: Option<Passphrase>,
+    // Client names mapped to their public keys, clients have to introduce themselves by that name.
+    #[serde(default)]
+    pub clients: HashMap<String, PublicKey>,
+}
+
+// Always TLS over TCP, regardless of the transport used for listening.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Dial {
+    // The listen address of the client, as hostname:port.
+    pub address: String,
+    pub certificate_path: PathBuf,
+    // Name the certificate of the client is checked against, defaults to the host of the address.
+    pub server_name: Option<String>,
+}
+
+impl Dial {
+    pub fn tls_name(&self) -> &str {
+        match &self.server_name {
+            Some(name) => name,
+            None => {
+                let host = self
+                    .address
+                    .rsplit_once(':')
+                    .map(|(host, _)| host)
+                    .unwrap_or(&self.address);
+
+                // IPv6 literals are enclosed in brackets, as in [fe80::1]:5258.
+                host.strip_prefix('[')
+                    .and_then(|host| host.strip_suffix(']'))
+                    .unwrap_or(host)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    // TLS over TCP.
+    #[default]
+    Tcp,
+    // QUIC, with mouse movement on a stream of its own.
+    Quic,
+    // WebSocket over TLS, for networks which only let HTTP(S) through.
+    #[serde(rename = "websocket")]
+    WebSocket,
+    // Noise over TCP, authenticated by keys and/or a passphrase instead of certificates.
+    Noise,
+}
+
+#[derive(Clone)]
+pub enum ListenAddress {
+    Ip(SocketAddr),
+    // Access is controlled by the permissions of the socket file and its directory.
+    Unix(PathBuf),
+    // For virtual machines on the same host, typically vsock:any:PORT.
+    Vsock(VsockAddr),
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Ip(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Vsock(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ListenAddressVisitor)
+    }
+}
+
+struct ListenAddressVisitor;
+
+impl<'de> Visitor<'de> for ListenAddressVisitor {
+    type Value = ListenAddress;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a socket address (ip:port), a Unix socket path (unix:/path) or a vsock address (vsock:cid:port)"
+        )
+    }
+
+    fn visit_str<E>(self, data: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        if let Some(path) = data.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(E::custom("Empty Unix socket path"));
+            }
+
+            return Ok(ListenAddress::Unix(path.into()));
+        }
+
+        if let Some(address) = data.strip_prefix("vsock:") {
+            return address.parse().map(ListenAddress::Vsock).map_err(E::custom);
+        }
+
+        data.parse()
+            .map(ListenAddress::Ip)
+            .map_err(|_| E::custom("Invalid listen address"))
+    }
 }
@@ -0,0 +1,136 @@
+use input::Event;
+use net::{DatagramDirection, DatagramKey, DatagramOpener, DatagramSealer, DatagramSetup};
+use std::collections::HashMap;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+struct Entry {
+    opener: DatagramOpener,
+    // Where the client has last been heard from.
+    peer: watch::Sender<Option<SocketAddr>>,
+}
+
+// The UDP socket shared by all clients, which are told apart by their session.
+#[derive(Clone)]
+pub struct Datagrams {
+    socket: Arc<UdpSocket>,
+    sessions: Arc<Mutex<HashMap<u64, Entry>>>,
+    next_session: Arc<AtomicU64>,
+}
+
+impl Datagrams {
+    pub async fn bind(address: SocketAddr) -> Result<Self, Error> {
+        let socket = Arc::new(UdpSocket::bind(address).await?);
+        let sessions = Arc::new(Mutex::new(HashMap::<u64, Entry>::new()));
+
+        let receiver = socket.clone();
+        let entries = sessions.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 2048];
+            loop {
+                let (length, address) = match receiver.recv_from(&mut buffer).await {
+                    Ok(received) => received,
+                    // ICMP errors from clients which went away are reported here on some platforms.
+                    Err(err) => {
+                        log::trace!("UDP receive error: {}", err);
+                        continue;
+                    }
+                };
+
+                let datagram = &buffer[..length];
+                let session = match net::session_of(datagram) {
+                    Some(session) => session,
+                    None => continue,
+                };
+
+                let mut entries = entries.lock().unwrap();
+                let entry = match entries.get_mut(&session) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+
+                // Clients only send empty datagrams, but they prove the client owns the address.
+                match entry.opener.open(datagram) {
+                    Ok(Some(_)) => {
+                        if *entry.peer.borrow() != Some(address) {
+                            log::debug!("Datagram session {} now at {}", session, address);
+                            let _ = entry.peer.send(Some(address));
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => log::trace!("{}: dropping datagram: {}", address, err),
+                }
+            }
+        });
+
+        Ok(Self {
+            socket,
+            sessions,
+            next_session: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn register(&self) -> Result<Session, Error> {
+        let setup = DatagramSetup {
+            port: self.socket.local_addr()?.port(),
+            session: self.next_session.fetch_add(1, Ordering::Relaxed),
+            key: DatagramKey::generate()?,
+        };
+
+        let (peer_sender, peer_receiver) = watch::channel(None);
+        self.sessions.lock().unwrap().insert(
+            setup.session,
+            Entry {
+                opener: DatagramOpener::new(&setup, DatagramDirection::ClientToServer),
+                peer: peer_sender,
+            },
+        );
+
+        Ok(Session {
+            setup,
+            sealer: DatagramSealer::new(&setup, DatagramDirection::ServerToClient),
+            peer: peer_receiver,
+            datagrams: self.clone(),
+        })
+    }
+}
+
+pub struct Session {
+    setup: DatagramSetup,
+    sealer: DatagramSealer,
+    peer: watch::Receiver<Option<SocketAddr>>,
+    datagrams: Datagrams,
+}
+
+impl Session {
+    pub fn setup(&self) -> DatagramSetup {
+        self.setup
+    }
+
+    // Returns false if the client has not been heard from yet, the frame then has to go over TCP.
+    pub async fn send(&mut self, frame: &[Event]) -> Result<bool, Error> {
+        let peer = match *self.peer.borrow() {
+            Some(peer) => peer,
+            None => return Ok(false),
+        };
+
+        let datagram = self.sealer.seal(frame)?;
+        self.datagrams.socket.send_to(&datagram, peer).await?;
+
+        Ok(true)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.datagrams
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(&self.setup.session);
+    }
+}
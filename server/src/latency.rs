@@ -0,0 +1,48 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+// Round trip time statistics of a client, computed from ping/pong exchanges.
+#[derive(Clone, Copy, Default)]
+pub struct Latency {
+    // The last measured round trip time.
+    pub last: Option<Duration>,
+    // Smoothed round trip time, as in RFC 6298.
+    pub smoothed: Duration,
+    // Mean deviation between consecutive samples, as in RFC 3550.
+    pub jitter: Duration,
+}
+
+impl Latency {
+    pub fn update(&mut self, sample: Duration) {
+        match self.last {
+            Some(last) => {
+                self.smoothed = (self.smoothed * 7 + sample) / 8;
+
+                let difference = sample.abs_diff(last);
+                self.jitter = if difference > self.jitter {
+                    self.jitter + (difference - self.jitter) / 16
+                } else {
+                    self.jitter - (self.jitter - difference) / 16
+                };
+            }
+            None => self.smoothed = sample,
+        }
+
+        self.last = Some(sample);
+    }
+}
+
+impl Display for Latency {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.last.is_none() {
+            return write!(f, "rtt unknown");
+        }
+
+        write!(
+            f,
+            "rtt {:.1} ms, jitter {:.1} ms",
+            self.smoothed.as_secs_f64() * 1000.0,
+            self.jitter.as_secs_f64() * 1000.0
+        )
+    }
+}
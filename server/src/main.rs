@@ -1,21 +1,43 @@
 mod config;
+mod datagram;
+mod latency;
+mod noise;
+mod queue;
+mod quic;
+mod resume;
+mod tls;
 
 use anyhow::{Context, Error};
-use config::Config;
-use input::{Direction, Event, EventManager, Key, KeyKind};
-use net::{self, Message, PROTOCOL_VERSION};
-use std::collections::{HashMap, HashSet};
+use config::{Config, Dial, ListenAddress, Transport};
+use datagram::Datagrams;
+use input::{Direction, Event, EventManager, KeyKind};
+use latency::Latency;
+use net::{self, Capabilities, Framing, KeepAlive, Message, Report, Role, SessionInfo, WebSocket};
+#[cfg(target_os = "linux")]
+use net::{VsockAddr, VsockListener};
+use resume::{SessionState, Sessions};
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::io;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+use tls::{Acceptor, Connector};
 use tokio::fs;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpListener;
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::io::{self as tokio_io, AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::{oneshot, watch};
 use tokio::time;
-use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+#[cfg(unix)]
+use {
+    std::fs::Permissions,
+    std::os::unix::fs::{FileTypeExt, PermissionsExt},
+    tokio::net::{UnixListener, UnixStream},
+};
 
 #[cfg(feature = "notify")]
 use notify_rust::Notification;
@@ -45,68 +67,572 @@ macro_rules! log_info {
 }
 
 
+// Sent to the main loop once a client has introduced itself.
+struct Registration {
+    name: String,
+    sender: queue::Sender,
+    latency: watch::Receiver<Latency>,
+    accepted: oneshot::Sender<bool>,
+}
+
+// Taken from the config, the same for every connection.
+#[derive(Clone, Copy)]
+struct Settings {
+    keep_alive: KeepAlive,
+    max_frame_size: u32,
+}
+
+// The other end of a connection.
+struct Peer {
+    address: String,
+    // What the client presented to authenticate itself, if required.
+    credential: Option<Credential>,
+}
+
+impl Peer {
+    fn new(address: String) -> Self {
+        Self {
+            address,
+            credential: None,
+        }
+    }
+}
+
+// Along with the name of the client it belongs to.
+enum Credential {
+    Certificate(String),
+    NoiseKey(String),
+}
+
+impl Credential {
+    fn name(&self) -> &str {
+        match self {
+            Self::Certificate(name) | Self::NoiseKey(name) => name,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Certificate(_) => "certificate",
+            Self::NoiseKey(_) => "Noise key",
+        }
+    }
+}
+
+// Secures connections accepted over TCP, depending on the transport.
+#[derive(Clone)]
+enum Security {
+    Tls(Acceptor),
+    Noise(noise::Acceptor),
+}
+
+impl Security {
+    async fn new(config: &Config) -> Result<Self, Error> {
+        if config.transport != Transport::Noise {
+            return Acceptor::new(config).await.map(Self::Tls);
+        }
+
+        if config.client_auth.is_some() {
+            return Err(anyhow::anyhow!(
+                "client-auth is not supported by the noise transport, list the keys of clients under noise instead"
+            ));
+        }
+
+        let noise = config
+            .noise
+            .as_ref()
+            .context("The noise transport requires noise to be set")?;
+
+        noise::Acceptor::new(noise).map(Self::Noise)
+    }
+
+    async fn accept<S>(
+        &self,
+        stream: S,
+    ) -> Result<(Box<dyn tls::Stream>, Option<Credential>), Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        match self {
+            Self::Tls(acceptor) => {
+                let (stream, name) = acceptor.accept(stream).await.context("TLS error")?;
+                Ok((stream, name.map(Credential::Certificate)))
+            }
+            Self::Noise(acceptor) => {
+                let (stream, name) = acceptor.accept(stream).await?;
+                Ok((stream, name.map(Credential::NoiseKey)))
+            }
+        }
+    }
+}
+
+struct Client {
+    name: String,
+    sender: queue::Sender,
+    latency: watch::Receiver<Latency>,
+}
+
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+// How long to wait before dialing a client in reverse-connect mode again.
+const REDIAL_INTERVAL: Duration = Duration::from_secs(5);
+
 async fn handle_connection<T>(
     mut stream: T,
-    mut receiver: UnboundedReceiver<Event>,
+    peer: &Peer,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+    datagrams: Option<Datagrams>,
+    mut motion: Option<Box<dyn AsyncWrite + Send + Unpin>>,
 ) -> Result<(), Error>
 where
     T: AsyncRead + AsyncWrite + Unpin,
 {
-    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
+    let address = peer.address.as_str();
+    let handshake = net::handshake(
+        &mut stream,
+        Role::Server,
+        Capabilities::SUPPORTED,
+        settings.keep_alive,
+    )
+    .await?;
+    let framing = handshake.framing.max_frame_size(settings.max_frame_size);
+    let keep_alive = handshake.keep_alive;
+    log::debug!(
+        "{}: using protocol version {} with capabilities {:?} and {:?}",
+        address,
+        handshake.version,
+        handshake.capabilities,
+        keep_alive
+    );
 
-    let version = net::read_version(&mut stream).await?;
-    if version != PROTOCOL_VERSION {
-        return Err(anyhow::anyhow!(
-            "Incompatible protocol version (got {}, expecting {})",
-            version,
-            PROTOCOL_VERSION
-        ));
-    }
+    // Clients predating the hello message can only be told apart by their address.
+    let name = if handshake.has_hello() {
+        let message = time::timeout(
+            keep_alive.timeout(),
+            net::read_message(&mut stream, &framing),
+        )
+        .await
+        .context("Read timeout")??;
 
-    loop {
-        // Send a keep alive message in intervals of half of the timeout just to be on the safe side.
-        let message = match time::timeout(net::MESSAGE_TIMEOUT / 2, receiver.recv()).await {
-            Ok(Some(message)) => Message::Event(message),
-            Ok(None) => return Ok(()),
-            Err(_) => Message::KeepAlive,
+        let hello = match message {
+            Message::Hello(hello) => hello,
+            message => return Err(anyhow::anyhow!("Expected hello, got {:?}", message)),
         };
 
-        log::trace!("sending {:?}", &message);
+        if hello.name.is_empty() {
+            return Err(anyhow::anyhow!("Client sent an empty name"));
+        }
+
+        // Otherwise a client could pass itself off as another one, taking over its session.
+        if let Some(credential) = &peer.credential {
+            if hello.name != credential.name() {
+                let message = Message::Rejected(format!(
+                    "The client {} belongs to {}, not {}",
+                    credential.kind(),
+                    credential.name(),
+                    hello.name
+                ));
+                let _ = time::timeout(
+                    keep_alive.timeout(),
+                    net::write_message(&mut stream, &message, &framing),
+                )
+                .await;
+
+                return Err(anyhow::anyhow!(
+                    "{} presented the {} of {}",
+                    hello.name,
+                    credential.kind(),
+                    credential.name()
+                ));
+            }
+        }
+
+        log_info!(
+            "{}: identified as {} (host {}, {}, rkvm {}{})",
+            address,
+            hello.name,
+            hello.hostname,
+            hello.os,
+            hello.version,
+            hello
+                .screen
+                .map(|screen| format!(", screen {}x{}", screen.width, screen.height))
+                .unwrap_or_default()
+        );
+
+        hello.name
+    } else {
+        peer.credential
+            .as_ref()
+            .map(|credential| credential.name().to_owned())
+            .unwrap_or_else(|| address.to_owned())
+    };
 
-        time::timeout(
-            net::MESSAGE_TIMEOUT,
-            net::write_message(&mut stream, &message),
+    let resume = if handshake.has_resume() {
+        let message = time::timeout(
+            keep_alive.timeout(),
+            net::read_message(&mut stream, &framing),
         )
         .await
-        .context("Write timeout")??;
+        .context("Read timeout")??;
+
+        match message {
+            Message::Resume(resume) => resume,
+            message => return Err(anyhow::anyhow!("Expected resume, got {:?}", message)),
+        }
+    } else {
+        None
+    };
+
+    let resumed = match resume {
+        Some(resume) => sessions
+            .resume(resume.ticket, &name, keep_alive.timeout())
+            .await
+            .map(|state| (state, resume.received)),
+        None => None,
+    };
+
+    // A resumed session is still known to the main loop, it only needs to catch up on what it missed.
+    let mut state = match resumed {
+        Some((state, received)) => {
+            log_info!("{}: resumed session", name);
+            write_messages(
+                &mut stream,
+                &state.replay.since(received),
+                &framing,
+                keep_alive.timeout(),
+            )
+            .await?;
+
+            state
+        }
+        None => {
+            sessions.discard(&name);
+
+            let (sender, receiver) = queue::channel();
+            let (latency_sender, latency_receiver) = watch::channel(Latency::default());
+            let (accepted_sender, accepted_receiver) = oneshot::channel();
+            let registration = Registration {
+                name: name.clone(),
+                sender,
+                latency: latency_receiver,
+                accepted: accepted_sender,
+            };
+
+            if registrations.send(Ok(registration)).is_err() {
+                return Ok(());
+            }
+
+            if !accepted_receiver.await.unwrap_or(false) {
+                let message =
+                    Message::Rejected(format!("A client named {} is already connected", name));
+                // Best effort, the client may well be gone by now.
+                let _ = time::timeout(
+                    keep_alive.timeout(),
+                    net::write_message(&mut stream, &message, &framing),
+                )
+                .await;
+
+                return Err(anyhow::anyhow!("Duplicate client name {}", name));
+            }
+
+            let state = SessionState::new(name.clone(), receiver, latency_sender);
+            sessions.create(state, handshake.has_resume())?
+        }
+    };
+
+    // Also lets the client release keys which were released while it was gone.
+    if let Some(ticket) = state.ticket() {
+        let info = SessionInfo {
+            ticket,
+            pressed: state.replay.pressed(),
+        };
+        write_messages(
+            &mut stream,
+            &[Message::Session(info)],
+            &framing,
+            keep_alive.timeout(),
+        )
+        .await?;
+    }
+
+    let mut session = match datagrams {
+        Some(datagrams) if handshake.capabilities.contains(Capabilities::DATAGRAM) => {
+            let session = datagrams.register()?;
+            time::timeout(
+                keep_alive.timeout(),
+                net::write_message(&mut stream, &Message::Datagram(session.setup()), &framing),
+            )
+            .await
+            .context("Write timeout")??;
+
+            Some(session)
+        }
+        _ => None,
+    };
+
+    let batched = handshake
+        .capabilities
+        .contains(Capabilities::BATCHED_FRAMES);
+    let pinging = handshake.capabilities.contains(Capabilities::PING);
+    let resumable = state.ticket().is_some();
+    // Acknowledgements are read by one half of the connection and acted upon by the other.
+    let acked = AtomicU64::new(0);
+    let taken_over = state.taken_over();
+    let SessionState {
+        receiver,
+        latency: latency_sender,
+        replay,
+        ..
+    } = &mut *state;
+    let (mut reader, mut writer) = tokio_io::split(stream);
+    // Ping timestamps are relative to this instant, the client just echoes them back.
+    let start = Instant::now();
+
+    let write = async {
+        // The client is expected to answer in time, so pings mustn't be any further apart than keep alives.
+        let mut ping = time::interval(PING_INTERVAL.min(keep_alive.interval()));
+
+        loop {
+            replay.acknowledge(acked.load(Ordering::Relaxed));
+
+            let (messages, lossy) = tokio::select! {
+                frame = receiver.recv() => match frame {
+                    Some(frame) => {
+                        let lossy = net::is_lossy(&frame);
+                        // Only frames which are fine to lose go over UDP, and only once the client has been heard from there.
+                        let sent = match &mut session {
+                            Some(session) if lossy => {
+                                session.send(&frame).await.unwrap_or_else(|err| {
+                                    log::debug!("{}: sending datagram failed: {}", name, err);
+                                    false
+                                })
+                            }
+                            _ => false,
+                        };
+
+                        let messages = if sent {
+                            Vec::new()
+                        } else if resumable && !lossy {
+                            // Only frames which would be missed are replayed, there's no point in replaying mouse movement.
+                            vec![replay.push(frame)]
+                        } else if batched {
+                            vec![Message::Frame(frame)]
+                        } else {
+                            // Older clients get one event at a time.
+                            frame.into_iter().map(Message::Event).collect()
+                        };
+
+                        (messages, lossy)
+                    }
+                    None if receiver.overflowed() => {
+                        return Err(anyhow::anyhow!("Too many events queued, the connection can't keep up"))
+                    }
+                    None => return Ok(()),
+                },
+                _ = ping.tick(), if pinging => (vec![Message::Ping(start.elapsed().as_micros() as u64)], false),
+                _ = time::sleep(keep_alive.interval()) => (vec![Message::KeepAlive], false),
+            };
+
+            // With QUIC, mouse movement has a stream of its own so that it's never stuck behind key events and vice versa.
+            match &mut motion {
+                Some(motion) if lossy => {
+                    write_messages(motion, &messages, &framing, keep_alive.timeout()).await?
+                }
+                _ => write_messages(&mut writer, &messages, &framing, keep_alive.timeout()).await?,
+            }
+        }
+    };
+
+    let read = async {
+        loop {
+            // Clients which don't answer pings may stay silent indefinitely,
+            // but we still want to learn about them disconnecting or reporting errors.
+            let message = if pinging {
+                time::timeout(
+                    keep_alive.timeout(),
+                    net::read_message(&mut reader, &framing),
+                )
+                .await
+                .context("Read timeout")??
+            } else {
+                net::read_message(&mut reader, &framing).await?
+            };
+
+            match message {
+                Message::Pong(timestamp) => {
+                    let sample = start
+                        .elapsed()
+                        .checked_sub(Duration::from_micros(timestamp))
+                        .ok_or_else(|| anyhow::anyhow!("Pong from the future"))?;
+
+                    let mut latency = *latency_sender.borrow();
+                    latency.update(sample);
+                    let _ = latency_sender.send(latency);
+
+                    log::debug!(
+                        "{}: rtt sample {:.1} ms ({})",
+                        name,
+                        sample.as_secs_f64() * 1000.0,
+                        latency
+                    );
+                }
+                Message::Ack(sequence) => {
+                    acked.fetch_max(sequence, Ordering::Relaxed);
+                }
+                Message::Report(Report::Status(status)) => {
+                    log_info!("{}: {}", name, status);
+                }
+                Message::Report(Report::InjectionFailed(err)) => {
+                    log::warn!("{}: failed to inject events: {}", name, err);
+                }
+                Message::Report(Report::Error(err)) => {
+                    log_error!("{}: error: {}", name, err);
+                }
+                message => return Err(anyhow::anyhow!("Unexpected message {:?}", message)),
+            }
+        }
+    };
+
+    let result = tokio::select! {
+        result = write => result,
+        result = read => result,
+        _ = taken_over => Err(anyhow::anyhow!("Session resumed on another connection")),
+    };
+
+    // Events were dropped, so the client has to start over rather than resume.
+    if state.receiver.overflowed() {
+        state.forget();
+
+        let message = Message::Rejected("Too many events queued, start a new session".to_owned());
+        let _ = time::timeout(
+            keep_alive.timeout(),
+            net::write_message(&mut writer, &message, &framing),
+        )
+        .await;
     }
+
+    result
 }
 
-async fn run(
-    listen_address: SocketAddr,
-    switch_keys: &HashSet<Key>,
-    identity_path: &Path,
-    identity_password: &str,
-) -> Result<Infallible, Error> {
-    let identity = fs::read(identity_path)
-        .await
-        .context("Failed to read identity")?;
-    let identity =
-        Identity::from_pkcs12(&identity, identity_password).context("Failed to parse identity")?;
-    let acceptor: tokio_native_tls::TlsAcceptor = TlsAcceptor::new(identity)
-        .context("Failed to create TLS acceptor")
-        .map(Into::into)?;
-    let listener = TcpListener::bind(listen_address).await?;
+async fn write_messages<W>(
+    writer: &mut W,
+    messages: &[Message],
+    framing: &Framing,
+    timeout: Duration,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    for message in messages {
+        log::trace!("sending {:?}", message);
 
-    log_info!("Listening on {}", listen_address);
+        time::timeout(timeout, net::write_message(&mut *writer, message, framing))
+            .await
+            .context("Write timeout")??;
+    }
+
+    Ok(())
+}
+
+// Forgets clients which have disconnected, keeping `current` pointed at the same client if possible.
+fn remove_closed(clients: &mut Vec<Client>, current: &mut usize) {
+    for idx in (0..clients.len()).rev() {
+        if !clients[idx].sender.is_closed() {
+            continue;
+        }
+
+        clients.remove(idx);
+        if *current == idx + 1 {
+            *current = 0;
+        } else if *current > idx + 1 {
+            *current -= 1;
+        }
+    }
+}
+
+// Logs the lifetime of a connection, handle_connection does the actual work.
+async fn serve_connection<T>(
+    stream: T,
+    peer: Peer,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+    datagrams: Option<Datagrams>,
+    motion: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    log_info!(
+        "{}: connected{}",
+        peer.address,
+        peer.credential
+            .as_ref()
+            .map(|credential| format!(" with the {} of {}", credential.kind(), credential.name()))
+            .unwrap_or_default()
+    );
+
+    let message = handle_connection(
+        stream,
+        &peer,
+        registrations,
+        sessions,
+        settings,
+        datagrams,
+        motion,
+    )
+    .await
+    .err()
+    .map(|err| format!(" ({})", err))
+    .unwrap_or_else(String::new);
+    log_info!("{}: disconnected{}", peer.address, message);
+}
+
+async fn listen_tcp(
+    config: &Config,
+    address: SocketAddr,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) -> Result<(), Error> {
+    let acceptor = Security::new(config).await?;
+    let listener = TcpListener::bind(address).await?;
+    let datagrams = if config.datagram {
+        Some(
+            Datagrams::bind(address)
+                .await
+                .context("Failed to bind UDP socket")?,
+        )
+    } else {
+        None
+    };
+
+    let websocket = config.transport == Transport::WebSocket;
+    let keep_alive = settings.keep_alive;
+    let tcp_keep_alive = config.keep_alive.tcp;
+
+    log_info!(
+        "Listening on {}{}",
+        address,
+        match config.transport {
+            Transport::WebSocket => " (WebSocket)",
+            Transport::Noise => " (Noise)",
+            _ => "",
+        }
+    );
+
+    // Clients pin the server by its public key, so make it easy to find.
+    if let Security::Noise(acceptor) = &acceptor {
+        log::info!("Noise public key: {}", acceptor.public_key());
+    }
 
-    let (client_sender, mut client_receiver) = mpsc::unbounded_channel();
     tokio::spawn(async move {
         loop {
             let (stream, address) = match listener.accept().await {
                 Ok(sa) => sa,
                 Err(err) => {
-                    let _ = client_sender.send(Err(err));
+                    let _ = registrations.send(Err(err));
                     return;
                 }
             };
@@ -115,94 +641,651 @@ async fn run(
                 log::warn!("{}: setting TCP_NODELAY failed: {}", address, err);
             };
 
-            let stream = match acceptor.accept(stream).await {
-                Ok(stream) => stream,
+            if tcp_keep_alive {
+                if let Err(err) = net::set_tcp_keep_alive(&stream, &keep_alive) {
+                    log::warn!("{}: setting TCP keepalive failed: {}", address, err);
+                }
+            }
+
+            // Handshakes run on their own, so that a client which never finishes its own doesn't hold up others.
+            let acceptor = acceptor.clone();
+            let registrations = registrations.clone();
+            let sessions = sessions.clone();
+            let datagrams = datagrams.clone();
+            tokio::spawn(async move {
+                let (stream, credential) =
+                    match time::timeout(keep_alive.timeout(), acceptor.accept(stream)).await {
+                        Ok(Ok(accepted)) => accepted,
+                        Ok(Err(err)) => {
+                            log_error!("{}: {:#}", address, err);
+                            return;
+                        }
+                        Err(_) => {
+                            log_error!("{}: handshake timed out", address);
+                            return;
+                        }
+                    };
+
+                let peer = Peer {
+                    address: address.to_string(),
+                    credential,
+                };
+
+                if !websocket {
+                    serve_connection(
+                        stream,
+                        peer,
+                        registrations,
+                        sessions,
+                        settings,
+                        datagrams,
+                        None,
+                    )
+                    .await;
+
+                    return;
+                }
+
+                let stream =
+                    match time::timeout(keep_alive.timeout(), WebSocket::accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(err)) => {
+                            log_error!("{}: WebSocket error: {}", address, err);
+                            return;
+                        }
+                        Err(_) => {
+                            log_error!("{}: WebSocket handshake timed out", address);
+                            return;
+                        }
+                    };
+
+                serve_connection(
+                    stream,
+                    peer,
+                    registrations,
+                    sessions,
+                    settings,
+                    datagrams,
+                    None,
+                )
+                .await;
+            });
+        }
+    });
+
+    Ok(())
+}
+
+// Unix sockets are protected by their permissions and vsock sockets by vsock-cids or client-auth,
+// so TLS is optional for them.
+#[cfg(unix)]
+async fn local_acceptor(config: &Config) -> Result<Option<Acceptor>, Error> {
+    if config.datagram {
+        return Err(anyhow::anyhow!(
+            "The datagram option requires an IP listen address"
+        ));
+    }
+
+    // Setting only one of the PEM files still asks for TLS, read_identity explains what's missing.
+    let identity = config.identity_path.is_some()
+        || config.certificate_path.is_some()
+        || config.key_path.is_some();
+    if !identity && config.client_auth.is_none() {
+        return Ok(None);
+    }
+
+    Acceptor::new(config).await.map(Some)
+}
+
+#[cfg(unix)]
+async fn serve_local<T>(
+    stream: T,
+    address: String,
+    acceptor: Option<Acceptor>,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    // Otherwise a peer never finishing the handshake would keep its task around for good.
+    let timeout = settings.keep_alive.timeout();
+    match acceptor {
+        Some(acceptor) => match time::timeout(timeout, acceptor.accept(stream)).await {
+            Ok(Ok((stream, certificate_name))) => {
+                serve_connection(
+                    stream,
+                    Peer {
+                        address,
+                        credential: certificate_name.map(Credential::Certificate),
+                    },
+                    registrations,
+                    sessions,
+                    settings,
+                    None,
+                    None,
+                )
+                .await
+            }
+            Ok(Err(err)) => {
+                log_error!("{}: TLS error: {:#}", address, err);
+            }
+            Err(_) => {
+                log_error!("{}: TLS handshake timed out", address);
+            }
+        },
+        None => {
+            serve_connection(
+                stream,
+                Peer::new(address),
+                registrations,
+                sessions,
+                settings,
+                None,
+                None,
+            )
+            .await
+        }
+    }
+}
+
+// With a mode set, the socket is bound in a directory only we can access, and only moved into place
+// once it has that mode, so that it's never reachable with the permissions left by the umask.
+#[cfg(unix)]
+async fn bind_unix(path: &Path, mode: Option<u32>) -> Result<UnixListener, Error> {
+    let mode = match mode {
+        Some(mode) => mode,
+        None => return UnixListener::bind(path).context("Failed to bind Unix socket"),
+    };
+
+    let name = path.file_name().context("Invalid Unix socket path")?;
+    let mut private = path.as_os_str().to_owned();
+    private.push(format!(".{}", process::id()));
+    let private = PathBuf::from(private);
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .await
+        .context("Failed to create directory for the Unix socket")?;
+
+    let bound = private.join(name);
+    let result = async {
+        let listener = UnixListener::bind(&bound).context("Failed to bind Unix socket")?;
+        fs::set_permissions(&bound, Permissions::from_mode(mode))
+            .await
+            .context("Failed to set socket permissions")?;
+        fs::rename(&bound, path)
+            .await
+            .context("Failed to move socket into place")?;
+
+        Ok(listener)
+    }
+    .await;
+
+    // The socket is only left behind if something failed before it was moved.
+    let _ = fs::remove_file(&bound).await;
+    let _ = fs::remove_dir(&private).await;
+
+    result
+}
+
+#[cfg(unix)]
+async fn listen_unix(
+    config: &Config,
+    path: &Path,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) -> Result<(), Error> {
+    let acceptor = local_acceptor(config).await?;
+
+    // A socket left behind by a previous run would make binding fail, but one still in use has to be left alone.
+    if let Ok(metadata) = fs::symlink_metadata(path).await {
+        if metadata.file_type().is_socket() {
+            match UnixStream::connect(path).await {
+                Ok(_) => return Err(anyhow::anyhow!("Another server is listening on the socket")),
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    fs::remove_file(path)
+                        .await
+                        .context("Failed to remove stale socket")?;
+                }
                 Err(err) => {
-                    log_error!("{}: TLS error: {}", address, err);
-                    continue;
+                    return Err(Error::new(err).context("Failed to check for a stale socket"))
+                }
+            }
+        }
+    }
+
+    let listener = bind_unix(path, config.socket_mode).await?;
+    log_info!(
+        "Listening on unix:{}{}",
+        path.display(),
+        if acceptor.is_some() { " (TLS)" } else { "" }
+    );
+
+    let path = path.display().to_string();
+    tokio::spawn(async move {
+        // Unix socket peers are usually unnamed, so number them to tell them apart.
+        for id in 1u64.. {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    let _ = registrations.send(Err(err));
+                    return;
                 }
             };
 
-            let (sender, receiver) = mpsc::unbounded_channel();
-            if client_sender.send(Ok(sender)).is_err() {
-                return;
+            tokio::spawn(serve_local(
+                stream,
+                format!("unix:{}#{}", path, id),
+                acceptor.clone(),
+                registrations.clone(),
+                sessions.clone(),
+                settings,
+            ));
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+async fn listen_vsock(
+    config: &Config,
+    address: VsockAddr,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) -> Result<(), Error> {
+    // Any guest can connect otherwise.
+    if config.vsock_cids.is_none() && config.client_auth.is_none() {
+        return Err(anyhow::anyhow!(
+            "A vsock listen address requires vsock-cids or client-auth to be set"
+        ));
+    }
+
+    let acceptor = local_acceptor(config).await?;
+    let listener = VsockListener::bind(address).context("Failed to bind vsock socket")?;
+    let cids = config.vsock_cids.clone();
+
+    log_info!(
+        "Listening on {}{}",
+        address,
+        if acceptor.is_some() { " (TLS)" } else { "" }
+    );
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(sa) => sa,
+                Err(err) => {
+                    let _ = registrations.send(Err(err));
+                    return;
+                }
+            };
+
+            if let Some(cids) = &cids {
+                if !cids.contains(&address.cid) {
+                    log::warn!("{}: CID not listed in vsock-cids", address);
+                    continue;
+                }
             }
 
+            tokio::spawn(serve_local(
+                stream,
+                address.to_string(),
+                acceptor.clone(),
+                registrations.clone(),
+                sessions.clone(),
+                settings,
+            ));
+        }
+    });
+
+    Ok(())
+}
+
+async fn listen_quic(
+    config: &Config,
+    address: SocketAddr,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) -> Result<(), Error> {
+    if config.datagram {
+        return Err(anyhow::anyhow!(
+            "The datagram option can't be used with QUIC, which already sends mouse movement separately"
+        ));
+    }
+
+    if config.client_auth.is_some() {
+        return Err(anyhow::anyhow!(
+            "client-auth is not supported by the QUIC transport"
+        ));
+    }
+
+    let endpoint = quic::bind(address, config).await?;
+
+    log_info!("Listening on {} (QUIC)", address);
+
+    tokio::spawn(async move {
+        while let Some(connecting) = endpoint.accept().await {
+            let address = connecting.remote_address();
+            let registrations = registrations.clone();
+            let sessions = sessions.clone();
             tokio::spawn(async move {
-                log_info!("{}: connected", address);
+                let (stream, motion) = match quic::accept(connecting).await {
+                    Ok(streams) => streams,
+                    Err(err) => {
+                        log_error!("{}: QUIC error: {}", address, err);
+                        return;
+                    }
+                };
 
-                let message = handle_connection(stream, receiver)
-                    .await
-                    .err()
-                    .map(|err| format!(" ({})", err))
-                    .unwrap_or_else(String::new);
-                log_info!("{}: disconnected{}", address, message);
+                serve_connection(
+                    stream,
+                    Peer::new(address.to_string()),
+                    registrations,
+                    sessions,
+                    settings,
+                    None,
+                    Some(Box::new(motion)),
+                )
+                .await;
             });
         }
+
+        let _ = registrations.send(Err(io::Error::other("QUIC endpoint closed")));
     });
 
-    let mut clients: Vec<UnboundedSender<Event>> = Vec::new();
+    Ok(())
+}
+
+// Keeps (re)connecting to a client which listens for the server, the session itself is the same as for inbound connections.
+async fn dial(
+    config: &Config,
+    name: &str,
+    dial: &Dial,
+    registrations: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) -> Result<(), Error> {
+    let certificate = fs::read(&dial.certificate_path)
+        .await
+        .with_context(|| format!("Failed to read certificate of {}", name))?;
+    let connector = Connector::new(config, &certificate)
+        .await
+        .with_context(|| format!("Failed to create connector for {}", name))?;
+
+    log_info!("Dialing {} ({})", name, dial.address);
+
+    let name = name.to_owned();
+    let address = dial.address.clone();
+    let tls_name = dial.tls_name().to_owned();
+    let keep_alive = settings.keep_alive;
+    let tcp_keep_alive = config.keep_alive.tcp;
+    tokio::spawn(async move {
+        loop {
+            // A client that stopped responding would otherwise keep us from ever redialing it.
+            let stream = async {
+                let stream = time::timeout(keep_alive.timeout(), TcpStream::connect(&address))
+                    .await
+                    .map_err(|_| net::Error::Timeout)??;
+                if let Err(err) = stream.set_nodelay(true) {
+                    log::warn!("{}: setting TCP_NODELAY failed: {}", address, err);
+                };
+
+                if tcp_keep_alive {
+                    if let Err(err) = net::set_tcp_keep_alive(&stream, &keep_alive) {
+                        log::warn!("{}: setting TCP keepalive failed: {}", address, err);
+                    }
+                }
+
+                time::timeout(keep_alive.timeout(), connector.connect(&tls_name, stream))
+                    .await
+                    .map_err(|_| net::Error::Timeout)?
+            };
+
+            match stream.await {
+                Ok(stream) => {
+                    serve_connection(
+                        stream,
+                        Peer::new(address.clone()),
+                        registrations.clone(),
+                        sessions.clone(),
+                        settings,
+                        None,
+                        None,
+                    )
+                    .await
+                }
+                Err(err) => log::debug!("Failed to dial {} ({}): {:#}", name, address, err),
+            }
+
+            if registrations.is_closed() {
+                return;
+            }
+
+            time::sleep(REDIAL_INTERVAL).await;
+        }
+    });
+
+    Ok(())
+}
+
+async fn listen(
+    config: &Config,
+    listen_address: &ListenAddress,
+    client_sender: UnboundedSender<Result<Registration, io::Error>>,
+    sessions: Sessions,
+    settings: Settings,
+) -> Result<(), Error> {
+    match (config.transport, listen_address) {
+        (Transport::Tcp, ListenAddress::Ip(address))
+        | (Transport::WebSocket, ListenAddress::Ip(address))
+        | (Transport::Noise, ListenAddress::Ip(address)) => {
+            listen_tcp(config, *address, client_sender, sessions, settings).await?
+        }
+        (Transport::Quic, ListenAddress::Ip(address)) => {
+            listen_quic(config, *address, client_sender, sessions, settings).await?
+        }
+        #[cfg(unix)]
+        (Transport::Tcp, ListenAddress::Unix(path)) => {
+            listen_unix(config, path, client_sender, sessions, settings).await?
+        }
+        #[cfg(not(unix))]
+        (Transport::Tcp, ListenAddress::Unix(_)) => {
+            return Err(anyhow::anyhow!(
+                "Unix sockets are not supported on this platform"
+            ))
+        }
+        #[cfg(target_os = "linux")]
+        (Transport::Tcp, ListenAddress::Vsock(address)) => {
+            listen_vsock(config, *address, client_sender, sessions, settings).await?
+        }
+        #[cfg(not(target_os = "linux"))]
+        (Transport::Tcp, ListenAddress::Vsock(_)) => {
+            return Err(anyhow::anyhow!("vsock is not supported on this platform"))
+        }
+        (transport, _) => {
+            return Err(anyhow::anyhow!(
+                "The {:?} transport requires an IP listen address",
+                transport
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+async fn run(config: &Config) -> Result<Infallible, Error> {
+    if config.listen_address.is_none() && config.dial.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Either listen-address or dial has to be set"
+        ));
+    }
+
+    let (client_sender, mut client_receiver) = mpsc::unbounded_channel();
+    let sessions = Sessions::default();
+    let keep_alive = config
+        .keep_alive
+        .to_net()
+        .context("keep-alive timeout-ms has to be at least twice interval-ms")?;
+    let settings = Settings {
+        keep_alive,
+        max_frame_size: config.max_frame_size.unwrap_or(net::DEFAULT_MAX_FRAME_SIZE),
+    };
+    if let Some(listen_address) = &config.listen_address {
+        listen(
+            config,
+            listen_address,
+            client_sender.clone(),
+            sessions.clone(),
+            settings,
+        )
+        .await?;
+    }
+
+    for (name, client) in &config.dial {
+        dial(
+            config,
+            name,
+            client,
+            client_sender.clone(),
+            sessions.clone(),
+            settings,
+        )
+        .await?;
+    }
+
+    let mut clients: Vec<Client> = Vec::new();
     let mut current = 0;
     let mut manager = EventManager::new().await?;
-    let mut key_states: HashMap<_, _> = switch_keys
+    let mut key_states: HashMap<_, _> = config
+        .switch_keys
         .iter()
         .copied()
         .map(|key| (key, false))
         .collect();
+    let mut status = match config.status_interval_secs.unwrap_or(60) {
+        0 => None,
+        secs => {
+            let period = Duration::from_secs(secs);
+            Some(time::interval_at(time::Instant::now() + period, period))
+        }
+    };
     loop {
         tokio::select! {
-            event = manager.read() => {
-                let event = event?;
+            frame = manager.read() => {
+                let mut frame = frame?;
                 let mut last_pressed_key = None;
-                if let Event::Key { direction, kind: KeyKind::Key(key) } = event {
-                    if let Some(state) = key_states.get_mut(&key) {
-                        *state = direction == Direction::Down;
-                        last_pressed_key = Some(key);
+                for event in &frame {
+                    if let Event::Key { direction, kind: KeyKind::Key(key) } = *event {
+                        if let Some(state) = key_states.get_mut(&key) {
+                            *state = direction == Direction::Down;
+                            last_pressed_key = Some(key);
+                        }
                     }
                 }
 
                 if key_states.iter().filter(|(_, state)| **state).count() == key_states.len() {
+                    // The press completing the combo is never seen by the current target,
+                    // everything else in the frame still goes there before switching.
+                    let completing = frame.iter().rposition(|event| {
+                        matches!(
+                            *event,
+                            Event::Key { direction: Direction::Down, kind: KeyKind::Key(key) }
+                                if Some(key) == last_pressed_key
+                        )
+                    });
+                    if let Some(idx) = completing {
+                        frame.remove(idx);
+                    }
+
                     for (key, state) in key_states.iter_mut() {
                         // Release all currently pressed keys from combo
                         // NOTE: This will NOT release other keys that are not part of the combo
                         if Some(*key) != last_pressed_key {
-                            let event = Event::Key{
+                            frame.push(Event::Key{
                                 direction: Direction::Up,
                                 kind: KeyKind::Key(*key),
-                            };
-                            if current == 0 {
-                                manager.write(event).await?;
-                            }else {
-                                let idx = current - 1;
-                                // We cannot remove broken client here, to not crash in next iteration,
-                                // and it will be removed later one anyways, therefore we just ignore error here
-                                let _ = clients[idx].send(event);
-                            }
+                            });
                         }
                         *state = false;
                     }
 
+                    if !frame.is_empty() {
+                        if current == 0 {
+                            manager.write_frame(&frame).await?;
+                        } else {
+                            let idx = current - 1;
+                            // We cannot remove broken client here, to not crash in next iteration,
+                            // and it will be removed later one anyways, therefore we just ignore error here
+                            let _ = clients[idx].sender.send(frame);
+                        }
+                    }
+
                     current = (current + 1) % (clients.len() + 1);
-                    log_info!("Switching to client {}", current);
+                    if current == 0 {
+                        log_info!("Switching to server");
+                    } else {
+                        let client = &clients[current - 1];
+                        log_info!("Switching to client {} ({})", client.name, *client.latency.borrow());
+                    }
+
                     continue;
                 }
 
                 if current != 0 {
                     let idx = current - 1;
-                    if clients[idx].send(event).is_ok() {
-                        continue;
+                    match clients[idx].sender.send(frame) {
+                        Ok(()) => continue,
+                        Err(rejected) => frame = rejected,
                     }
 
                     clients.remove(idx);
                     current = 0;
                 };
 
-                log::trace!("writing: {:?}", &event);
+                log::trace!("writing: {:?}", &frame);
 
-                manager.write(event).await?;
+                manager.write_frame(&frame).await?;
             }
-            sender = client_receiver.recv() => {
-                clients.push(sender.unwrap()?);
+            // The branch is disabled if unset, so the future is never polled then.
+            _ = async { status.as_mut().unwrap().tick().await }, if status.is_some() => {
+                remove_closed(&mut clients, &mut current);
+                for client in &clients {
+                    log::info!("{}: {}", client.name, *client.latency.borrow());
+                }
+            }
+            registration = client_receiver.recv() => {
+                let registration = registration.unwrap()?;
+
+                // A client reconnecting under the same name must not be mistaken for a duplicate.
+                remove_closed(&mut clients, &mut current);
+                if clients.iter().any(|client| client.name == registration.name) {
+                    let _ = registration.accepted.send(false);
+                    continue;
+                }
+
+                // Clients listed in the config are ordered accordingly, the rest go last in order of connection.
+                let order = &config.clients;
+                let rank = |name: &str| order.iter().position(|n| n == name).unwrap_or(order.len());
+                let idx = clients
+                    .iter()
+                    .position(|client| rank(&client.name) > rank(&registration.name))
+                    .unwrap_or(clients.len());
+                if current > idx {
+                    current += 1;
+                }
+
+                clients.insert(idx, Client {
+                    name: registration.name,
+                    sender: registration.sender,
+                    latency: registration.latency,
+                });
+
+                let _ = registration.accepted.send(true);
             }
         }
     }
@@ -247,7 +1330,7 @@ async fn main() {
     };
 
     tokio::select! {
-        result = run(config.listen_address, &config.switch_keys, &config.identity_path, &config.identity_password) => {
+        result = run(&config) => {
             if let Err(err) = result {
                 log_error!("Error: {:#}", err);
                 process::exit(1);
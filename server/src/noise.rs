@@ -0,0 +1,68 @@
+use crate::config::Noise as Config;
+use crate::tls::Stream;
+use anyhow::{Context, Error};
+use net::{Noise, Passphrase, PrivateKey, PublicKey};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[derive(Clone)]
+pub struct Acceptor {
+    key: Arc<PrivateKey>,
+    passphrase: Option<Arc<Passphrase>>,
+    names: Arc<HashMap<PublicKey, String>>,
+}
+
+impl Acceptor {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let names = config
+            .clients
+            .iter()
+            .map(|(name, key)| (*key, name.clone()))
+            .collect::<HashMap<_, _>>();
+
+        // Anyone could connect otherwise.
+        if names.is_empty() && config.passphrase.is_none() {
+            return Err(anyhow::anyhow!(
+                "The noise transport requires passphrase or clients to be set"
+            ));
+        }
+
+        let key = match &config.private_key {
+            Some(key) => key.clone(),
+            None => PrivateKey::generate().context("Failed to generate Noise key")?,
+        };
+
+        Ok(Self {
+            key: Arc::new(key),
+            passphrase: config.passphrase.clone().map(Arc::new),
+            names: Arc::new(names),
+        })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.key.public_key()
+    }
+
+    // Also returns the name of the client the key it presented belongs to, if clients are listed.
+    pub async fn accept<S>(&self, stream: S) -> Result<(Box<dyn Stream>, Option<String>), Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let stream = Noise::accept(stream, &self.key, self.passphrase.as_deref())
+            .await
+            .context("Noise handshake failed")?;
+        if self.names.is_empty() {
+            return Ok((Box::new(stream), None));
+        }
+
+        // Logged along with the key, so that it can be added to the config.
+        let name = self
+            .names
+            .get(stream.remote_key())
+            .with_context(|| format!("Unknown client key {}", stream.remote_key()))?
+            .clone();
+
+        Ok((Box::new(stream), Some(name)))
+    }
+}
@@ -0,0 +1,307 @@
+use input::{Axis, Event, Scroll};
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+// Clients this far behind are disconnected rather than queueing up even more.
+const MAX_QUEUED_EVENTS: usize = 4096;
+
+// Frames waiting to be written to a client.
+//
+// As long as the connection keeps up, frames come out just as they went in. Once it falls behind,
+// mouse movement and scrolling queued in between other frames is merged into a single frame,
+// so that key and button events only ever wait for one frame of motion and stale motion isn't
+// replayed in a burst. Other frames are never reordered, a click has to land where the pointer was moved to.
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            frames: VecDeque::new(),
+            queued: 0,
+            motion: Motion::default(),
+            sender: true,
+            receiver: true,
+            overflowed: false,
+        }),
+        notify: Notify::new(),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+struct State {
+    frames: VecDeque<Vec<Event>>,
+    // The number of events in frames.
+    queued: usize,
+    // Motion received after the last frame in frames.
+    motion: Motion,
+    sender: bool,
+    receiver: bool,
+    overflowed: bool,
+}
+
+impl State {
+    fn pop(&mut self) -> Option<Vec<Event>> {
+        match self.frames.pop_front() {
+            Some(frame) => {
+                self.queued -= frame.len();
+                Some(frame)
+            }
+            None => self.motion.take(),
+        }
+    }
+}
+
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    // Gives the frame back if the receiver is gone or the client has fallen too far behind.
+    pub fn send(&self, frame: Vec<Event>) -> Result<(), Vec<Event>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.receiver || state.overflowed {
+            return Err(frame);
+        }
+
+        if net::is_lossy(&frame) {
+            state.motion.add(&frame);
+        } else {
+            if let Some(motion) = state.motion.take() {
+                state.queued += motion.len();
+                state.frames.push_back(motion);
+            }
+
+            if state.queued + frame.len() > MAX_QUEUED_EVENTS {
+                state.overflowed = true;
+                state.frames.clear();
+                state.queued = 0;
+                drop(state);
+
+                self.shared.notify.notify_one();
+                return Err(frame);
+            }
+
+            state.queued += frame.len();
+            state.frames.push_back(frame);
+        }
+
+        drop(state);
+        self.shared.notify.notify_one();
+
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        !state.receiver || state.overflowed
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().sender = false;
+        self.shared.notify.notify_one();
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    // None once the sender is gone or the client has fallen too far behind.
+    pub async fn recv(&mut self) -> Option<Vec<Event>> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.overflowed {
+                    return None;
+                }
+
+                if let Some(frame) = state.pop() {
+                    return Some(frame);
+                }
+
+                if !state.sender {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.shared.state.lock().unwrap().overflowed
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver = false;
+    }
+}
+
+// Deltas of consecutive motion events, summed up.
+#[derive(Default)]
+struct Motion {
+    x: i32,
+    y: i32,
+    scroll: [i32; 3],
+}
+
+impl Motion {
+    const SCROLLS: [Scroll; 3] = [Scroll::Lo, Scroll::HiRes, Scroll::HiResH];
+
+    fn add(&mut self, frame: &[Event]) {
+        for event in frame {
+            let (total, delta) = match *event {
+                Event::MouseMove {
+                    axis: Axis::X,
+                    delta,
+                } => (&mut self.x, delta),
+                Event::MouseMove {
+                    axis: Axis::Y,
+                    delta,
+                } => (&mut self.y, delta),
+                Event::MouseScroll { scroll, delta } => {
+                    let index = match scroll {
+                        Scroll::Lo => 0,
+                        Scroll::HiRes => 1,
+                        Scroll::HiResH => 2,
+                    };
+
+                    (&mut self.scroll[index], delta)
+                }
+                Event::Key { .. } => continue,
+            };
+
+            *total = total.saturating_add(delta);
+        }
+    }
+
+    // The merged frame, None if there was no motion since the last call or it all cancelled out.
+    fn take(&mut self) -> Option<Vec<Event>> {
+        let motion = mem::take(self);
+        let mut frame = Vec::new();
+        for (axis, delta) in [(Axis::X, motion.x), (Axis::Y, motion.y)] {
+            if delta != 0 {
+                frame.push(Event::MouseMove { axis, delta });
+            }
+        }
+
+        for (scroll, delta) in Self::SCROLLS.iter().copied().zip(motion.scroll) {
+            if delta != 0 {
+                frame.push(Event::MouseScroll { scroll, delta });
+            }
+        }
+
+        Some(frame).filter(|frame| !frame.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::{Direction, Key, KeyKind};
+
+    fn key() -> Vec<Event> {
+        vec![Event::Key {
+            direction: Direction::Down,
+            kind: KeyKind::Key(Key::A),
+        }]
+    }
+
+    fn motion(x: i32, y: i32) -> Vec<Event> {
+        vec![
+            Event::MouseMove {
+                axis: Axis::X,
+                delta: x,
+            },
+            Event::MouseMove {
+                axis: Axis::Y,
+                delta: y,
+            },
+        ]
+    }
+
+    // Events don't implement PartialEq.
+    async fn received(receiver: &mut Receiver) -> String {
+        format!("{:?}", receiver.recv().await)
+    }
+
+    fn expected(frame: Vec<Event>) -> String {
+        format!("{:?}", Some(frame))
+    }
+
+    #[tokio::test]
+    async fn merge() {
+        let (sender, mut receiver) = channel();
+        sender.send(motion(1, 2)).unwrap();
+        sender.send(motion(3, -2)).unwrap();
+        sender
+            .send(vec![Event::MouseScroll {
+                scroll: Scroll::Lo,
+                delta: 1,
+            }])
+            .unwrap();
+
+        // Y cancelled out.
+        let frame = vec![
+            Event::MouseMove {
+                axis: Axis::X,
+                delta: 4,
+            },
+            Event::MouseScroll {
+                scroll: Scroll::Lo,
+                delta: 1,
+            },
+        ];
+        assert_eq!(received(&mut receiver).await, expected(frame));
+
+        drop(sender);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn ordering() {
+        let (sender, mut receiver) = channel();
+        sender.send(motion(1, 1)).unwrap();
+        sender.send(motion(1, 1)).unwrap();
+        sender.send(key()).unwrap();
+        sender.send(motion(5, 5)).unwrap();
+
+        // Motion is never merged across key frames.
+        assert_eq!(received(&mut receiver).await, expected(motion(2, 2)));
+        assert_eq!(received(&mut receiver).await, expected(key()));
+        assert_eq!(received(&mut receiver).await, expected(motion(5, 5)));
+    }
+
+    #[tokio::test]
+    async fn overflow() {
+        let (sender, mut receiver) = channel();
+        for _ in 0..MAX_QUEUED_EVENTS {
+            sender.send(key()).unwrap();
+        }
+
+        // Motion is merged, so it doesn't count until it's followed by another frame.
+        sender.send(motion(1, 1)).unwrap();
+        assert!(!sender.is_closed());
+
+        assert!(sender.send(key()).is_err());
+        assert!(sender.is_closed());
+        assert!(receiver.overflowed());
+        assert!(receiver.recv().await.is_none());
+    }
+}
@@ -0,0 +1,27 @@
+use crate::config::Config;
+use crate::tls;
+use anyhow::{Context, Error};
+use net::Duplex;
+use quinn::{Connecting, Endpoint, RecvStream, SendStream, ServerConfig};
+use std::net::SocketAddr;
+
+pub async fn bind(address: SocketAddr, config: &Config) -> Result<Endpoint, Error> {
+    let (certificates, key) = tls::read_identity(config).await?;
+
+    let config = ServerConfig::with_single_cert(certificates, key)
+        .context("Failed to create QUIC config")?;
+
+    Endpoint::server(config, address).context("Failed to bind QUIC endpoint")
+}
+
+// The client opens a bidirectional stream carrying the same messages as a TCP connection would,
+// we then open a unidirectional one for mouse movement so that neither blocks the other.
+pub async fn accept(
+    connecting: Connecting,
+) -> Result<(Duplex<RecvStream, SendStream>, SendStream), Error> {
+    let connection = connecting.await?;
+    let (send, receive) = connection.accept_bi().await?;
+    let motion = connection.open_uni().await?;
+
+    Ok((Duplex::new(receive, send), motion))
+}
@@ -0,0 +1,380 @@
+use crate::latency::Latency;
+use crate::queue::Receiver;
+use input::{Direction, Event, KeyKind};
+use net::{Message, SessionTicket};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Error;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, OwnedMutexGuard};
+use tokio::time;
+
+// Older frames are forgotten, the client then only gets its pressed keys reconciled on resumption.
+const MAX_UNACKED: usize = 1024;
+
+// Everything about a client which has to survive a lost connection.
+pub struct SessionState {
+    pub name: String,
+    pub receiver: Receiver,
+    pub latency: watch::Sender<Latency>,
+    pub replay: Replay,
+    // That of the connection which held the session last, only set once it actually got hold of it.
+    generation: u64,
+}
+
+impl SessionState {
+    pub fn new(name: String, receiver: Receiver, latency: watch::Sender<Latency>) -> Self {
+        Self {
+            name,
+            receiver,
+            latency,
+            replay: Replay {
+                unacked: VecDeque::new(),
+                next: 1,
+                pressed: HashSet::new(),
+            },
+            generation: 0,
+        }
+    }
+}
+
+// Frames sent to the client which it hasn't acknowledged yet.
+pub struct Replay {
+    unacked: VecDeque<(u64, Vec<Event>)>,
+    next: u64,
+    // Keys and buttons the client has been told to press and not to release yet.
+    pressed: HashSet<KeyKind>,
+}
+
+impl Replay {
+    pub fn push(&mut self, frame: Vec<Event>) -> Message {
+        let sequence = self.next;
+        self.next += 1;
+
+        for event in &frame {
+            if let Event::Key { direction, kind } = *event {
+                match direction {
+                    Direction::Down => self.pressed.insert(kind),
+                    Direction::Up => self.pressed.remove(&kind),
+                };
+            }
+        }
+
+        if self.unacked.len() == MAX_UNACKED {
+            self.unacked.pop_front();
+        }
+
+        self.unacked.push_back((sequence, frame.clone()));
+        Message::SequencedFrame(sequence, frame)
+    }
+
+    pub fn acknowledge(&mut self, sequence: u64) {
+        while matches!(self.unacked.front(), Some((first, _)) if *first <= sequence) {
+            self.unacked.pop_front();
+        }
+    }
+
+    // The frames the client missed, given the last one it received.
+    pub fn since(&self, received: u64) -> Vec<Message> {
+        self.unacked
+            .iter()
+            .filter(|(sequence, _)| *sequence > received)
+            .map(|(sequence, frame)| Message::SequencedFrame(*sequence, frame.clone()))
+            .collect()
+    }
+
+    pub fn pressed(&self) -> Vec<KeyKind> {
+        self.pressed.iter().copied().collect()
+    }
+}
+
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    state: Arc<tokio::sync::Mutex<SessionState>>,
+    // Bumped whenever a connection is about to take the session over, the previous one then gives it up.
+    owner: Arc<watch::Sender<u64>>,
+}
+
+impl Entry {
+    // Whether no connection holds the session.
+    fn is_idle(&self) -> bool {
+        self.state.try_lock().is_ok()
+    }
+}
+
+// Sessions which can be resumed, whether their connection is still alive or not.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    entries: Arc<Mutex<HashMap<SessionTicket, Entry>>>,
+}
+
+impl Sessions {
+    // Sessions which can't be resumed aren't kept around after their connection is gone.
+    pub fn create(&self, state: SessionState, resumable: bool) -> Result<SessionGuard, Error> {
+        let name = state.name.clone();
+        let state = Arc::new(tokio::sync::Mutex::new(state));
+        let entry = Entry {
+            name,
+            state: state.clone(),
+            owner: Arc::new(watch::channel(0).0),
+        };
+
+        let ticket = if resumable {
+            let ticket = SessionTicket::generate()?;
+            self.entries.lock().unwrap().insert(ticket, entry.clone());
+
+            Some(ticket)
+        } else {
+            None
+        };
+
+        Ok(SessionGuard {
+            state: state.try_lock_owned().unwrap(),
+            ticket,
+            generation: 0,
+            entry,
+            sessions: self.clone(),
+        })
+    }
+
+    // Forgets sessions of a client which connected again without resuming, e.g. because it was restarted.
+    // Otherwise it would be rejected as a duplicate until they expire.
+    pub fn discard(&self, name: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.name != name || !entry.is_idle());
+    }
+
+    // Waits for the connection currently holding the session, if any, to give it up.
+    pub async fn resume(
+        &self,
+        ticket: SessionTicket,
+        name: &str,
+        timeout: Duration,
+    ) -> Option<SessionGuard> {
+        let entry = self.entries.lock().unwrap().get(&ticket).cloned()?;
+        if entry.name != name {
+            return None;
+        }
+
+        let mut generation = 0;
+        entry.owner.send_modify(|owner| {
+            *owner += 1;
+            generation = *owner;
+        });
+
+        let mut state = time::timeout(timeout, entry.state.clone().lock_owned())
+            .await
+            .ok()?;
+
+        // Another connection may have resumed the session in the meantime.
+        if *entry.owner.borrow() != generation {
+            return None;
+        }
+
+        state.generation = generation;
+
+        Some(SessionGuard {
+            state,
+            ticket: Some(ticket),
+            generation,
+            entry,
+            sessions: self.clone(),
+        })
+    }
+}
+
+// Exclusive access to a session for the lifetime of a connection.
+pub struct SessionGuard {
+    state: OwnedMutexGuard<SessionState>,
+    ticket: Option<SessionTicket>,
+    generation: u64,
+    entry: Entry,
+    sessions: Sessions,
+}
+
+impl SessionGuard {
+    pub fn ticket(&self) -> Option<SessionTicket> {
+        self.ticket
+    }
+
+    // Makes the session impossible to resume, it's gone along with the guard.
+    pub fn forget(&mut self) {
+        if let Some(ticket) = self.ticket.take() {
+            self.sessions.entries.lock().unwrap().remove(&ticket);
+        }
+    }
+
+    // Resolves once another connection has taken the session over.
+    pub fn taken_over(&self) -> impl std::future::Future<Output = ()> {
+        let mut owner = self.entry.owner.subscribe();
+        let generation = self.generation;
+
+        async move {
+            while *owner.borrow_and_update() == generation {
+                if owner.changed().await.is_err() {
+                    return std::future::pending().await;
+                }
+            }
+        }
+    }
+}
+
+impl Deref for SessionGuard {
+    type Target = SessionState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+impl DerefMut for SessionGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.state
+    }
+}
+
+// Forgets the session unless it's resumed in time. Its receiver is dropped then,
+// which is how the main loop learns that the client is gone.
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let ticket = match self.ticket {
+            Some(ticket) => ticket,
+            None => return,
+        };
+
+        let generation = self.generation;
+        let sessions = self.sessions.clone();
+        tokio::spawn(async move {
+            time::sleep(net::RESUME_TIMEOUT).await;
+
+            let mut entries = sessions.entries.lock().unwrap();
+            // Unless another connection has held the session since, which takes care of it when gone.
+            let idle = match entries.get(&ticket) {
+                Some(entry) => {
+                    matches!(entry.state.try_lock(), Ok(state) if state.generation == generation)
+                }
+                None => false,
+            };
+
+            if idle {
+                let entry = entries.remove(&ticket).unwrap();
+                log::debug!("{}: session expired", entry.name);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue;
+    use input::{Axis, Key};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn key(direction: Direction) -> Event {
+        Event::Key {
+            direction,
+            kind: KeyKind::Key(Key::A),
+        }
+    }
+
+    fn motion(delta: i32) -> Event {
+        Event::MouseMove {
+            axis: Axis::X,
+            delta,
+        }
+    }
+
+    fn state(name: &str) -> SessionState {
+        let (_, receiver) = queue::channel();
+        SessionState::new(
+            name.to_owned(),
+            receiver,
+            watch::channel(Latency::default()).0,
+        )
+    }
+
+    fn sequences(messages: &[Message]) -> Vec<u64> {
+        messages
+            .iter()
+            .map(|message| match message {
+                Message::SequencedFrame(sequence, _) => *sequence,
+                _ => panic!("Unexpected message {:?}", message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replay() {
+        let mut replay = state("test").replay;
+        for delta in 1..=3 {
+            replay.push(vec![motion(delta)]);
+        }
+
+        assert_eq!(sequences(&replay.since(0)), [1, 2, 3]);
+        assert_eq!(sequences(&replay.since(2)), [3]);
+
+        replay.acknowledge(2);
+        assert_eq!(sequences(&replay.since(0)), [3]);
+
+        replay.acknowledge(3);
+        assert!(replay.since(0).is_empty());
+    }
+
+    #[test]
+    fn replay_overflow() {
+        let mut replay = state("test").replay;
+        replay.push(vec![key(Direction::Down)]);
+        for delta in 0..MAX_UNACKED as i32 {
+            replay.push(vec![motion(delta)]);
+        }
+
+        // The oldest frame is forgotten, but the key it pressed is still known.
+        let since = sequences(&replay.since(0));
+        assert_eq!(since.len(), MAX_UNACKED);
+        assert_eq!(since[0], 2);
+        assert_eq!(replay.pressed(), [KeyKind::Key(Key::A)]);
+
+        replay.push(vec![key(Direction::Up)]);
+        assert!(replay.pressed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn takeover() {
+        let sessions = Sessions::default();
+        let first = sessions.create(state("test"), true).unwrap();
+        let ticket = first.ticket().unwrap();
+        assert!(sessions.resume(ticket, "other", TIMEOUT).await.is_none());
+
+        let taken_over = first.taken_over();
+        let resume = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.resume(ticket, "test", TIMEOUT).await.is_some() }
+        });
+
+        // The first connection gives the session up once told to.
+        taken_over.await;
+        drop(first);
+        assert!(resume.await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn takeover_timeout() {
+        let sessions = Sessions::default();
+        let first = sessions.create(state("test"), true).unwrap();
+        let ticket = first.ticket().unwrap();
+
+        // The first connection doesn't give the session up in time.
+        assert!(sessions.resume(ticket, "test", TIMEOUT).await.is_none());
+        drop(first);
+
+        // The session still expires rather than being kept around forever.
+        time::sleep(net::RESUME_TIMEOUT * 2).await;
+        assert!(sessions.entries.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::config::{ClientAuth, Config};
+use anyhow::{Context, Error};
+use net::Fingerprint;
+use rustls::server::{AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, DistinguishedName, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(not(feature = "rustls"))]
+use tokio_native_tls::native_tls::{self, Identity, TlsAcceptor, TlsConnector};
+#[cfg(feature = "rustls")]
+use {rustls::ClientConfig, rustls::ServerName, std::convert::TryFrom};
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Either the native-tls or the rustls feature has to be enabled");
+
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+#[derive(Clone)]
+pub enum Acceptor {
+    #[cfg(not(feature = "rustls"))]
+    NativeTls(tokio_native_tls::TlsAcceptor),
+    // Also used by native-tls builds when client certificates are required, as it can't verify them on all platforms.
+    Rustls(tokio_rustls::TlsAcceptor, Option<Arc<ClientVerifier>>),
+}
+
+impl Acceptor {
+    pub async fn new(config: &Config) -> Result<Self, Error> {
+        #[cfg(not(feature = "rustls"))]
+        {
+            if config.client_auth.is_none() {
+                let identity = read_native_identity(config).await?;
+                let acceptor =
+                    TlsAcceptor::new(identity).context("Failed to create TLS acceptor")?;
+
+                return Ok(Self::NativeTls(acceptor.into()));
+            }
+        }
+
+        let (certificates, key) = read_identity(config).await?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let (builder, verifier) = match &config.client_auth {
+            Some(client_auth) => {
+                let verifier = Arc::new(ClientVerifier::new(client_auth).await?);
+                (
+                    builder.with_client_cert_verifier(verifier.clone()),
+                    Some(verifier),
+                )
+            }
+            None => (builder.with_no_client_auth(), None),
+        };
+
+        let config = builder
+            .with_single_cert(certificates, key)
+            .context("Failed to create TLS acceptor")?;
+
+        Ok(Self::Rustls(Arc::new(config).into(), verifier))
+    }
+
+    // Also returns the name of the client the certificate it presented belongs to, if required.
+    pub async fn accept<S>(&self, stream: S) -> Result<(Box<dyn Stream>, Option<String>), Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        match self {
+            #[cfg(not(feature = "rustls"))]
+            Self::NativeTls(acceptor) => {
+                let stream = acceptor.accept(stream).await?;
+                Ok((Box::new(stream), None))
+            }
+            Self::Rustls(acceptor, verifier) => {
+                let stream = acceptor.accept(stream).await?;
+                let verifier = match verifier {
+                    Some(verifier) => verifier,
+                    None => return Ok((Box::new(stream), None)),
+                };
+
+                let certificate = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certificates| certificates.first())
+                    .context("No client certificate")?;
+                let name = verifier.name(certificate)?;
+
+                Ok((Box::new(stream), Some(name)))
+            }
+        }
+    }
+}
+
+// Dials clients in reverse-connect mode, trusting nothing but their certificate and presenting our identity,
+// as the client in turn only accepts servers it knows.
+pub struct Connector {
+    #[cfg(not(feature = "rustls"))]
+    connector: tokio_native_tls::TlsConnector,
+    #[cfg(feature = "rustls")]
+    connector: tokio_rustls::TlsConnector,
+}
+
+impl Connector {
+    // The certificate may be either PEM or DER.
+    #[cfg(not(feature = "rustls"))]
+    pub async fn new(config: &Config, certificate: &[u8]) -> Result<Self, Error> {
+        let certificate = native_tls::Certificate::from_der(certificate)
+            .or_else(|_| native_tls::Certificate::from_pem(certificate))
+            .context("Failed to parse certificate")?;
+        let identity = read_native_identity(config).await?;
+        let connector = TlsConnector::builder()
+            .add_root_certificate(certificate)
+            .identity(identity)
+            .build()
+            .context("Failed to create TLS connector")?;
+
+        Ok(Self {
+            connector: connector.into(),
+        })
+    }
+
+    #[cfg(feature = "rustls")]
+    pub async fn new(config: &Config, certificate: &[u8]) -> Result<Self, Error> {
+        let mut certificates =
+            rustls_pemfile::certs(&mut &*certificate).context("Failed to parse certificate")?;
+        // Not a PEM file, presumably DER then.
+        if certificates.is_empty() {
+            certificates.push(certificate.to_vec());
+        }
+
+        let mut roots = RootCertStore::empty();
+        for certificate in certificates {
+            roots
+                .add(&Certificate(certificate))
+                .context("Failed to parse certificate")?;
+        }
+
+        let (certificates, key) = read_identity(config).await?;
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certificates, key)
+            .context("Failed to create TLS connector")?;
+
+        Ok(Self {
+            connector: Arc::new(config).into(),
+        })
+    }
+
+    #[cfg(not(feature = "rustls"))]
+    pub async fn connect<S>(
+        &self,
+        tls_name: &str,
+        stream: S,
+    ) -> Result<tokio_native_tls::TlsStream<S>, net::Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connector
+            .connect(tls_name, stream)
+            .await
+            .map_err(net::Error::tls)
+    }
+
+    #[cfg(feature = "rustls")]
+    pub async fn connect<S>(
+        &self,
+        tls_name: &str,
+        stream: S,
+    ) -> Result<tokio_rustls::client::TlsStream<S>, net::Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let tls_name = ServerName::try_from(tls_name).map_err(|err| net::Error::Tls(err.into()))?;
+        self.connector
+            .connect(tls_name, stream)
+            .await
+            .map_err(net::Error::tls)
+    }
+}
+
+// Accepts client certificates listed by fingerprint or issued by the configured CA.
+pub struct ClientVerifier {
+    names: HashMap<Fingerprint, String>,
+    ca: Option<AllowAnyAuthenticatedClient>,
+}
+
+impl ClientVerifier {
+    async fn new(config: &ClientAuth) -> Result<Self, Error> {
+        let names = config
+            .fingerprints
+            .iter()
+            .map(|(name, fingerprint)| (*fingerprint, name.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let ca = match &config.ca_path {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in read_certificates(path).await? {
+                    roots
+                        .add(&certificate)
+                        .context("Failed to parse CA certificate")?;
+                }
+
+                Some(AllowAnyAuthenticatedClient::new(roots))
+            }
+            None => None,
+        };
+
+        if names.is_empty() && ca.is_none() {
+            return Err(anyhow::anyhow!(
+                "client-auth requires ca-path or fingerprints to be set"
+            ));
+        }
+
+        Ok(Self { names, ca })
+    }
+
+    // Certificates listed by fingerprint go by the name they are listed under,
+    // those issued by the CA by the common name of their subject, unless that name is listed.
+    fn name(&self, certificate: &Certificate) -> Result<String, Error> {
+        let fingerprint =
+            Fingerprint::of(&certificate.0).context("Failed to parse client certificate")?;
+        if let Some(name) = self.names.get(&fingerprint) {
+            return Ok(name.clone());
+        }
+
+        let (_, certificate) = x509_parser::parse_x509_certificate(&certificate.0)
+            .context("Failed to parse client certificate")?;
+        let name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok())
+            .context("Client certificate has no common name")?;
+
+        // Otherwise a certificate from the CA could pose as a client pinned by fingerprint.
+        if self.names.values().any(|pinned| pinned == name) {
+            return Err(anyhow::anyhow!(
+                "Client certificate names {}, which is pinned to another certificate",
+                name
+            ));
+        }
+
+        Ok(name.to_owned())
+    }
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        match &self.ca {
+            Some(ca) => ca.client_auth_root_subjects(),
+            None => &[],
+        }
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let fingerprint = Fingerprint::of(&end_entity.0)
+            .ok_or_else(|| rustls::Error::General("Malformed client certificate".to_owned()))?;
+        if self.names.contains_key(&fingerprint) {
+            return Ok(ClientCertVerified::assertion());
+        }
+
+        match &self.ca {
+            Some(ca) => ca.verify_client_cert(end_entity, intermediates, now),
+            None => Err(rustls::Error::General(format!(
+                "Unknown client certificate {}",
+                fingerprint
+            ))),
+        }
+    }
+}
+
+// PEM files as written by rkvm-certificate-gen.
+pub async fn read_certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let certificates = fs::read(path).await.context("Failed to read certificate")?;
+    let certificates = rustls_pemfile::certs(&mut certificates.as_slice())
+        .context("Failed to parse certificate")?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    Ok(certificates)
+}
+
+pub async fn read_key(path: &Path) -> Result<PrivateKey, Error> {
+    let key = fs::read(path).await.context("Failed to read key")?;
+    rustls_pemfile::read_all(&mut key.as_slice())
+        .context("Failed to parse key")?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .context("No private key found")
+}
+
+// certificate-path and key-path if set, identity-path otherwise.
+#[cfg(not(feature = "rustls"))]
+async fn read_native_identity(config: &Config) -> Result<Identity, Error> {
+    if let (Some(certificate_path), Some(key_path)) = (&config.certificate_path, &config.key_path) {
+        let certificate = fs::read(certificate_path)
+            .await
+            .context("Failed to read certificate")?;
+        let key = fs::read(key_path).await.context("Failed to read key")?;
+
+        // Only PKCS#8 keys are supported, as written by OpenSSL 1.1 and later.
+        return Identity::from_pkcs8(&certificate, &key)
+            .context("Failed to parse certificate and key");
+    }
+
+    let identity_path = config
+        .identity_path
+        .as_deref()
+        .context("TLS requires identity-path or certificate-path and key-path to be set")?;
+    let identity = fs::read(identity_path)
+        .await
+        .context("Failed to read identity")?;
+
+    Identity::from_pkcs12(&identity, &config.identity_password).context("Failed to parse identity")
+}
+
+// The same for rustls, also used by the QUIC transport.
+pub async fn read_identity(config: &Config) -> Result<(Vec<Certificate>, PrivateKey), Error> {
+    if let (Some(certificate_path), Some(key_path)) = (&config.certificate_path, &config.key_path) {
+        return Ok((
+            read_certificates(certificate_path).await?,
+            read_key(key_path).await?,
+        ));
+    }
+
+    if let Some(identity_path) = &config.identity_path {
+        let identity = fs::read(identity_path)
+            .await
+            .context("Failed to read identity")?;
+
+        return read_pkcs12(&identity, &config.identity_password)
+            .context("Failed to parse identity");
+    }
+
+    Err(anyhow::anyhow!(
+        "TLS requires identity-path or certificate-path and key-path to be set"
+    ))
+}
+
+fn read_pkcs12(identity: &[u8], password: &str) -> Result<(Vec<Certificate>, PrivateKey), Error> {
+    let keystore = p12_keystore::KeyStore::from_pkcs12(identity, password)?;
+    let (_, chain) = keystore
+        .private_key_chain()
+        .context("No private key found")?;
+
+    let certificates = chain
+        .chain()
+        .iter()
+        .map(|certificate| Certificate(certificate.as_der().to_vec()))
+        .collect();
+
+    Ok((certificates, PrivateKey(chain.key().to_vec())))
+}